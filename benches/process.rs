@@ -0,0 +1,102 @@
+//! Measures the parsing of received data with `Telnet::feed`, on mostly text traffic with some
+//! negotiations and subnegotiations, and on plain text alone.
+//!
+//! Run with `cargo bench --bench process`.
+//!
+//! Skipping from one IAC to the next with `memchr` instead of matching every byte took, on one
+//! machine:
+//!
+//! | traffic | before                   | after                    |
+//! |---------|--------------------------|--------------------------|
+//! | mixed   | 292 µs/64 KiB, 214 MiB/s | 315 µs/64 KiB, 199 MiB/s |
+//! | text    | 106 µs/64 KiB, 587 MiB/s | 6.5 µs/64 KiB, 9.5 GiB/s |
+//!
+//! The mixed traffic has an IAC every 50 bytes or so, and its time goes to the events rather than
+//! to the search; the difference there is within the noise.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+use telnet::{Event, Stream, Telnet};
+
+const SIZE: usize = 64 * 1024;
+
+// A stream which never has data, and discards everything written to it
+struct Idle;
+
+impl Stream for Idle {
+    fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Idle {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Idle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn telnet() -> Telnet {
+    #[cfg(feature = "zcstream")]
+    let stream = Box::new(telnet::ZlibStream::from_stream(Idle));
+    #[cfg(not(feature = "zcstream"))]
+    let stream = Box::new(Idle);
+    Telnet::from_stream(stream, 4096)
+}
+
+fn feed(telnet: &mut Telnet, data: &[u8]) {
+    telnet.feed(black_box(data));
+    // Take the events, until the stream reports the end of the connection
+    loop {
+        match telnet.read_nonblocking() {
+            Ok(Event::NoData) | Err(_) => break,
+            Ok(event) => {
+                black_box(event);
+            }
+        }
+    }
+}
+
+fn process(c: &mut Criterion) {
+    // A line of text, a negotiation, a NAWS subnegotiation and an escaped IAC
+    let mut chunk = b"You are standing in an open field west of a white house.\r\n".to_vec();
+    chunk.extend_from_slice(&[0xff, 0xfb, 0x01]);
+    chunk.extend_from_slice(b"There is a small mailbox here.\r\n");
+    chunk.extend_from_slice(&[0xff, 0xfa, 0x1f, 0, 80, 0, 24, 0xff, 0xf0]);
+    chunk.extend_from_slice(b"A price of 255 is \xff\xff.\r\n");
+    let mixed: Vec<u8> = chunk.iter().copied().cycle().take(SIZE).collect();
+
+    let text: Vec<u8> = b"The quick brown fox jumps over the lazy dog. "
+        .iter()
+        .copied()
+        .cycle()
+        .take(SIZE)
+        .collect();
+
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    for (name, data) in [("mixed", &mixed), ("text", &text)] {
+        let mut telnet = telnet();
+        group.bench_function(name, |b| b.iter(|| feed(&mut telnet, data)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, process);
+criterion_main!(benches);
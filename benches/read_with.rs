@@ -0,0 +1,115 @@
+//! Counts the allocations made while reading a large data stream with `Telnet::read`, which
+//! queues every read as an `Event::Data`, and with `Telnet::read_with`, which borrows the data
+//! from the read buffer.
+//!
+//! Run with `cargo bench --bench read_with`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    io::{self, Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+use telnet::{EventRef, Stream, Telnet};
+
+const SIZE: usize = 16 * 1024 * 1024;
+const BUF_SIZE: usize = 4096;
+
+// Counts every allocation made by the process
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// A stream which sends `left` bytes of text, then closes
+struct Text {
+    left: usize,
+}
+
+impl Stream for Text {
+    fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Text {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = buf.len().min(self.left);
+        buf[..size].fill(b'x');
+        self.left -= size;
+        Ok(size)
+    }
+}
+
+impl Write for Text {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn telnet() -> Telnet {
+    let stream = Text { left: SIZE };
+    #[cfg(feature = "zcstream")]
+    let stream = telnet::ZlibStream::from_stream(stream);
+    Telnet::from_stream(Box::new(stream), BUF_SIZE)
+}
+
+fn bench(name: &str, mut read_all: impl FnMut(&mut Telnet) -> usize) {
+    let mut telnet = telnet();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let received = read_all(&mut telnet);
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    assert_eq!(received, SIZE);
+    println!("{name:>9}: {allocations} allocations, {elapsed:?} for {SIZE} bytes");
+}
+
+fn main() {
+    bench("read", |telnet| {
+        let mut received = 0;
+        while let Ok(event) = telnet.read() {
+            if let telnet::Event::Data(data) = event {
+                received += black_box(data).len();
+            }
+        }
+        received
+    });
+
+    bench("read_with", |telnet| {
+        let mut received = 0;
+        while telnet
+            .read_with(|event| {
+                if let EventRef::Data(data) = event {
+                    received += black_box(data).len();
+                }
+            })
+            .is_ok()
+        {}
+        received
+    });
+}
@@ -0,0 +1,80 @@
+//! Compares `Telnet::write` on data without IAC bytes, which takes the fast path, against data
+//! full of IAC bytes, which has to be escaped.
+//!
+//! Run with `cargo bench --bench write`.
+
+use std::{
+    hint::black_box,
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+use telnet::{Stream, Telnet};
+
+const SIZE: usize = 64 * 1024;
+const ITERATIONS: u32 = 1000;
+
+// A stream which discards everything written to it
+struct Sink;
+
+impl Stream for Sink {
+    fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for Sink {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(black_box(buf).len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn telnet() -> Telnet {
+    #[cfg(feature = "zcstream")]
+    let stream = Box::new(telnet::ZlibStream::from_stream(Sink));
+    #[cfg(not(feature = "zcstream"))]
+    let stream = Box::new(Sink);
+    Telnet::from_stream(stream, 256)
+}
+
+fn bench(name: &str, data: &[u8]) {
+    let mut telnet = telnet();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        telnet.write(black_box(data)).unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{name:>10}: {:?} per write of {} bytes",
+        elapsed / ITERATIONS,
+        data.len()
+    );
+}
+
+fn main() {
+    let text: Vec<u8> = b"The quick brown fox jumps over the lazy dog. "
+        .iter()
+        .copied()
+        .cycle()
+        .take(SIZE)
+        .collect();
+    bench("IAC-free", &text);
+
+    let iac_heavy: Vec<u8> = [b'a', 0xff].iter().copied().cycle().take(SIZE).collect();
+    bench("IAC-heavy", &iac_heavy);
+}
@@ -0,0 +1,95 @@
+//! Helpers for the Authentication option (RFC 2941).
+
+const AUTH_IS: u8 = 0;
+const AUTH_SEND: u8 = 1;
+
+// The authentication type which stands for no authentication
+const AUTH_NULL: u8 = 0;
+
+/// An authentication type offered by the server, with its modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthType {
+    pub auth_type: u8,
+    pub modifiers: u8,
+}
+
+/// The reply of a client to the authentication types offered by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthReply {
+    /// None of the offered types is supported (`IS NULL`)
+    Reject,
+    /// Authentication data for one of the offered types
+    Is(AuthType, Box<[u8]>),
+}
+
+impl AuthReply {
+    /// Encodes the reply into a subnegotiation payload.
+    #[allow(clippy::must_use_candidate)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            AuthReply::Reject => vec![AUTH_IS, AUTH_NULL, 0],
+            AuthReply::Is(auth, data) => {
+                let mut buf = vec![AUTH_IS, auth.auth_type, auth.modifiers];
+                buf.extend_from_slice(data);
+                buf
+            }
+        }
+    }
+}
+
+/// Decodes the authentication types offered by an Authentication `SEND`, or returns `None` if the
+/// payload is not a `SEND`.
+#[allow(clippy::must_use_candidate)]
+pub fn parse_send(data: &[u8]) -> Option<Vec<AuthType>> {
+    let (&AUTH_SEND, pairs) = data.split_first()? else {
+        return None;
+    };
+    if pairs.len() % 2 != 0 {
+        return None;
+    }
+
+    Some(
+        pairs
+            .chunks(2)
+            .map(|pair| AuthType {
+                auth_type: pair[0],
+                modifiers: pair[1],
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_offered_types() {
+        assert_eq!(
+            parse_send(&[1, 2, 0, 6, 2]),
+            Some(vec![
+                AuthType {
+                    auth_type: 2,
+                    modifiers: 0
+                },
+                AuthType {
+                    auth_type: 6,
+                    modifiers: 2
+                },
+            ])
+        );
+        assert_eq!(parse_send(&[0, 2, 0]), None);
+        assert_eq!(parse_send(&[1, 2]), None);
+    }
+
+    #[test]
+    fn encodes_replies() {
+        assert_eq!(AuthReply::Reject.to_bytes(), [0, 0, 0]);
+
+        let auth = AuthType {
+            auth_type: 6,
+            modifiers: 2,
+        };
+        assert_eq!(AuthReply::Is(auth, Box::new([9])).to_bytes(), [0, 6, 2, 9]);
+    }
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+/// How long [`Telnet::connect_retry`](crate::Telnet::connect_retry) waits between attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Wait the same time after every attempt
+    Fixed(Duration),
+    /// Wait `initial` after the first attempt, then twice as long after each one, up to `max`
+    Exponential { initial: Duration, max: Duration },
+}
+
+impl Backoff {
+    // The time to wait after the attempt with the given index, counting from 0
+    pub(crate) fn delay(self, attempt: u32) -> Duration {
+        match self {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => initial
+                .checked_mul(2u32.saturating_pow(attempt))
+                .map_or(max, |delay| delay.min(max)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_exponential_delay_up_to_max() {
+        let backoff = Backoff::Exponential {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+        };
+        let delays: Vec<_> = (0..5).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [10, 20, 40, 50, 50].map(Duration::from_millis).to_vec()
+        );
+        assert_eq!(backoff.delay(100), Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,235 @@
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(windows)]
+use std::os::windows::io::RawSocket;
+use std::{
+    io::{Error, ErrorKind, Read, Result, Write},
+    net::{Shutdown, SocketAddr},
+    time::Duration,
+};
+
+use crate::{stream::Stream, TStream};
+
+/// A stream which collects small writes in a buffer, and passes them on to the wrapped stream
+/// in a single write once the buffer is full or flushed.
+pub struct BufferedStream {
+    stream: Box<TStream>,
+    buf: Vec<u8>,
+}
+
+impl BufferedStream {
+    pub fn new(stream: Box<TStream>, capacity: usize) -> BufferedStream {
+        BufferedStream {
+            stream,
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    // Write out the buffer. If the stream fails partway, only the bytes it did not take are
+    // left in the buffer, so that nothing is written twice.
+    fn flush_buf(&mut self) -> Result<()> {
+        while !self.buf.is_empty() {
+            match self.stream.write(&self.buf) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(size) => {
+                    self.buf.drain(..size);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BufferedStream {
+    fn drop(&mut self) {
+        // Errors cannot be reported from here
+        let _ = self.flush_buf();
+    }
+}
+
+impl Read for BufferedStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for BufferedStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // If the buffer cannot be written out, none of `buf` is taken
+        if self.buf.len() + buf.len() > self.buf.capacity() {
+            self.flush_buf()?;
+        }
+
+        // A write too large for the buffer is passed on directly
+        if buf.len() >= self.buf.capacity() {
+            return self.stream.write(buf);
+        }
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_buf()?;
+        self.stream.flush()
+    }
+}
+
+impl Stream for BufferedStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        self.stream.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        self.stream.set_write_timeout(dur)
+    }
+
+    fn write_timeout(&self) -> Result<Option<Duration>> {
+        self.stream.write_timeout()
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+        self.stream.set_nodelay(nodelay)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.stream.shutdown(how)
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    fn available(&self) -> Result<usize> {
+        self.stream.available()
+    }
+
+    fn set_cork(&self, cork: bool) -> Result<()> {
+        self.stream.set_cork(cork)
+    }
+
+    fn take_error(&self) -> Result<Option<Error>> {
+        self.stream.take_error()
+    }
+
+    fn set_linger(&self, linger: Option<Duration>) -> Result<()> {
+        self.stream.set_linger(linger)
+    }
+
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        self.stream.as_raw_fd()
+    }
+
+    #[cfg(windows)]
+    fn as_raw_socket(&self) -> Option<RawSocket> {
+        self.stream.as_raw_socket()
+    }
+}
+
+#[cfg(feature = "zcstream")]
+impl crate::zcstream::ZCStream for BufferedStream {
+    fn begin_zlib(&mut self) {
+        self.stream.begin_zlib();
+    }
+
+    fn begin_zlib_with_input(&mut self, input: &[u8]) {
+        self.stream.begin_zlib_with_input(input);
+    }
+
+    fn begin_zlib_with_dictionary(&mut self, dictionary: &[u8]) -> Result<()> {
+        self.stream.begin_zlib_with_dictionary(dictionary)
+    }
+
+    fn end_zlib(&mut self) {
+        self.stream.end_zlib();
+    }
+
+    fn is_zlib_active(&self) -> bool {
+        self.stream.is_zlib_active()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "zcstream")]
+    use crate::zlibstream::ZlibStream;
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    // Takes `budget` bytes, then would block
+    struct ShortStream {
+        wire: Rc<RefCell<Vec<u8>>>,
+        budget: Rc<Cell<usize>>,
+    }
+
+    impl Read for ShortStream {
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for ShortStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let size = self.budget.get().min(buf.len());
+            if size == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            self.budget.set(self.budget.get() - size);
+            self.wire.borrow_mut().extend_from_slice(&buf[..size]);
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Stream for ShortStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn keeps_unsent_bytes_on_failed_flush() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let budget = Rc::new(Cell::new(2));
+        let stream = ShortStream {
+            wire: Rc::clone(&wire),
+            budget: Rc::clone(&budget),
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut stream = BufferedStream::new(Box::new(stream), 8);
+
+        assert_eq!(stream.write(b"abcd").unwrap(), 4);
+        assert_eq!(stream.flush().unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(stream.buf, b"cd");
+
+        // A write which needs the buffer written out takes nothing while it cannot be
+        let err = stream.write(b"efghijk").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        assert_eq!(stream.buf, b"cd");
+
+        budget.set(usize::MAX);
+        stream.flush().unwrap();
+        assert_eq!(*wire.borrow(), b"abcd");
+    }
+}
@@ -4,4 +4,7 @@ pub const BYTE_DO: u8 = 253; // please, you use option
 pub const BYTE_WONT: u8 = 252; // I won't use option
 pub const BYTE_WILL: u8 = 251; // I will use option
 pub const BYTE_SB: u8 = 250; // interpret as subnegotiation
+pub const BYTE_EL: u8 = 248; // erase the current line
+pub const BYTE_AO: u8 = 245; // abort output
+pub const BYTE_IP: u8 = 244; // interrupt process
 pub const BYTE_SE: u8 = 240; // end sub negotiation
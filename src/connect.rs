@@ -0,0 +1,58 @@
+use crate::{Action, TelnetOption, DEFAULT_BUF_SIZE};
+use socket2::SockRef;
+use std::{io, net::TcpStream, time::Duration};
+
+/// The settings of a connection opened with
+/// [`Telnet::connect_with_options`](crate::Telnet::connect_with_options).
+///
+/// # Examples
+/// ```rust
+/// use std::time::Duration;
+/// use telnet::{Action, ConnectOptions, TelnetOption};
+///
+/// let options = ConnectOptions {
+///     connect_timeout: Some(Duration::from_secs(5)),
+///     nodelay: true,
+///     initial_negotiations: vec![(Action::Do, TelnetOption::SuppressGoAhead)],
+///     ..ConnectOptions::default()
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectOptions {
+    /// The size of the buffer for reading from the remote host, 4096 bytes by default
+    pub buf_size: usize,
+    /// How long to wait for the connection to be established, or without limit if `None`
+    pub connect_timeout: Option<Duration>,
+    /// How long `Telnet::read` waits for an event, see `Telnet::set_read_timeout`
+    pub read_timeout: Option<Duration>,
+    /// Whether to disable Nagle's algorithm (`TCP_NODELAY`)
+    pub nodelay: bool,
+    /// Whether to enable TCP keepalive probes (`SO_KEEPALIVE`)
+    pub keepalive: bool,
+    /// The negotiations sent as soon as the connection is established
+    pub initial_negotiations: Vec<(Action, TelnetOption)>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            buf_size: DEFAULT_BUF_SIZE,
+            connect_timeout: None,
+            read_timeout: None,
+            nodelay: false,
+            keepalive: false,
+            initial_negotiations: Vec::new(),
+        }
+    }
+}
+
+impl ConnectOptions {
+    // Set the socket options on a freshly connected stream
+    pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if self.keepalive {
+            SockRef::from(stream).set_keepalive(true)?;
+        }
+        Ok(())
+    }
+}
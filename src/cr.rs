@@ -0,0 +1,106 @@
+// Handling of bare CR bytes in received data
+
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+const NUL: u8 = 0;
+
+/// What to do with a bare CR in received data.
+///
+/// In the NVT, a CR must be followed by either LF or NUL. Some remote hosts send a CR on its own
+/// anyway, which confuses line parsers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BareCrPolicy {
+    /// Deliver bare CRs as they are
+    #[default]
+    Passthrough,
+    /// Drop bare CRs
+    Ignore,
+    /// Replace bare CRs with LF
+    StripToLf,
+}
+
+impl BareCrPolicy {
+    /// Applies the policy to `data`. A CR at the end of `data` cannot be told apart from a bare
+    /// one yet, so it is held back in `pending` until the next data.
+    pub(crate) fn apply(self, pending: &mut bool, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + 1);
+        for &byte in data {
+            if std::mem::take(pending) {
+                if byte == LF || byte == NUL {
+                    out.push(CR);
+                } else {
+                    out.extend(self.bare_cr());
+                }
+            }
+
+            if byte == CR && self != BareCrPolicy::Passthrough {
+                *pending = true;
+            } else {
+                out.push(byte);
+            }
+        }
+        out
+    }
+
+    /// What a held back CR becomes once the data ends without anything following it.
+    pub(crate) fn bare_cr(self) -> Option<u8> {
+        match self {
+            BareCrPolicy::Passthrough => Some(CR),
+            BareCrPolicy::Ignore => None,
+            BareCrPolicy::StripToLf => Some(LF),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Apply `policy` to `reads` in turn, then end the data
+    fn apply_all(policy: BareCrPolicy, reads: &[&[u8]]) -> Vec<u8> {
+        let mut pending = false;
+        let mut out = Vec::new();
+        for data in reads {
+            out.extend(policy.apply(&mut pending, data));
+        }
+        if pending {
+            out.extend(policy.bare_cr());
+        }
+        out
+    }
+
+    #[test]
+    fn passes_bare_cr_through() {
+        let policy = BareCrPolicy::Passthrough;
+        assert_eq!(apply_all(policy, &[b"a\rb\r\nc\r\0d"]), b"a\rb\r\nc\r\0d");
+        assert_eq!(apply_all(policy, &[b"a\r", b"\nb"]), b"a\r\nb");
+        assert_eq!(apply_all(policy, &[b"a\r"]), b"a\r");
+    }
+
+    #[test]
+    fn ignores_bare_cr() {
+        let policy = BareCrPolicy::Ignore;
+        assert_eq!(apply_all(policy, &[b"a\rb\r\nc\r\0d"]), b"ab\r\nc\r\0d");
+        assert_eq!(apply_all(policy, &[b"a\r", b"\nb\r", b"c"]), b"a\r\nbc");
+        assert_eq!(apply_all(policy, &[b"a\r"]), b"a");
+    }
+
+    #[test]
+    fn strips_bare_cr_to_lf() {
+        let policy = BareCrPolicy::StripToLf;
+        assert_eq!(apply_all(policy, &[b"a\rb\r\nc\r\0d"]), b"a\nb\r\nc\r\0d");
+        assert_eq!(apply_all(policy, &[b"a\r", b"\0b\r", b"c"]), b"a\r\0b\nc");
+        assert_eq!(apply_all(policy, &[b"a\r"]), b"a\n");
+    }
+
+    #[test]
+    fn holds_back_cr_at_end_of_data() {
+        let mut pending = false;
+        assert_eq!(BareCrPolicy::StripToLf.apply(&mut pending, b"a\r"), b"a");
+        assert!(pending);
+        assert_eq!(BareCrPolicy::StripToLf.apply(&mut pending, b"\r"), b"\n");
+        assert!(pending);
+        assert_eq!(BareCrPolicy::StripToLf.apply(&mut pending, b"\n"), b"\r\n");
+        assert!(!pending);
+    }
+}
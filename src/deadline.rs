@@ -0,0 +1,80 @@
+use crate::{Event, Telnet};
+use std::{
+    io::{self, ErrorKind},
+    time::{Duration, Instant},
+};
+
+/// A `Telnet` whose reads and writes share a single deadline. See [`Telnet::with_deadline`].
+pub struct Deadline<'a> {
+    telnet: &'a mut Telnet,
+    deadline: Instant,
+}
+
+impl<'a> Deadline<'a> {
+    pub(crate) fn new(telnet: &'a mut Telnet, deadline: Instant) -> Deadline<'a> {
+        Deadline { telnet, deadline }
+    }
+
+    /// The time left before the deadline.
+    #[allow(clippy::must_use_candidate)]
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Reads an [`Event`] like [`Telnet::read_timeout`], waiting no longer than the deadline.
+    /// Returns [`Event::TimedOut`] once the deadline has passed.
+    ///
+    /// # Errors
+    /// - The errors of [`Telnet::read_timeout`]
+    pub fn read(&mut self) -> io::Result<Event> {
+        let remaining = self.remaining();
+        if remaining.is_zero() {
+            return Ok(Event::TimedOut);
+        }
+        self.telnet.read_timeout(remaining)
+    }
+
+    /// Writes data like [`Telnet::write`]. Where the stream supports write timeouts, the write
+    /// waits no longer than the deadline, and the stream's own write timeout is put back after.
+    /// The stream is put in blocking mode for the write, so that it waits at all.
+    ///
+    /// If the deadline passes after part of the data was sent, the number of bytes sent is
+    /// returned, and the next write fails.
+    ///
+    /// # Errors
+    /// - [`ErrorKind::TimedOut`] if the deadline has passed, before the write or during it
+    /// - Write to stream fails
+    pub fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let remaining = self.remaining();
+        if remaining.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "deadline passed"));
+        }
+
+        // Without a way to read the write timeout back, it is left alone
+        let previous = match self.telnet.stream.write_timeout() {
+            Ok(previous) => match self.telnet.stream.set_write_timeout(Some(remaining)) {
+                Ok(()) => Some(previous),
+                Err(e) if e.kind() == ErrorKind::Unsupported => None,
+                Err(e) => return Err(e),
+            },
+            Err(e) if e.kind() == ErrorKind::Unsupported => None,
+            Err(e) => return Err(e),
+        };
+        self.telnet.set_stream_nonblocking(false)?;
+        let result = self.telnet.write(data);
+        if let Some(previous) = previous {
+            self.telnet.stream.set_write_timeout(previous)?;
+        }
+
+        // An expired write timeout is reported as either `WouldBlock` or `TimedOut`
+        match result {
+            Err(e)
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                    && self.remaining().is_zero() =>
+            {
+                Err(io::Error::new(ErrorKind::TimedOut, "deadline passed"))
+            }
+            result => result,
+        }
+    }
+}
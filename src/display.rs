@@ -0,0 +1,68 @@
+//! Rendering of received data for safe display on a terminal or in a log.
+
+use std::{convert::TryFrom, fmt::Write};
+
+/// The control characters passed through by [`sanitize`]: tab, line feed and carriage return.
+pub const DEFAULT_PASSTHROUGH: &[u8] = b"\t\n\r";
+
+/// Renders `data` as text, replacing control characters other than tab, line feed and carriage
+/// return, so that they cannot act on the terminal it is displayed on.
+///
+/// See [`sanitize_with`] for how each byte is rendered.
+///
+/// # Examples
+/// ```rust
+/// use telnet::display;
+///
+/// assert_eq!(display::sanitize(b"\x1b[2Jhi\r\n"), "^[[2Jhi\r\n");
+/// ```
+#[allow(clippy::must_use_candidate)]
+pub fn sanitize(data: &[u8]) -> String {
+    sanitize_with(data, DEFAULT_PASSTHROUGH)
+}
+
+/// Renders `data` as text, passing through only the control characters in `passthrough`.
+///
+/// The other ASCII control characters are rendered in caret notation (`^C` for 0x03, `^?` for
+/// DEL), the other Unicode control characters as `\u{..}`, and the bytes which are not valid
+/// UTF-8 as `\x..`.
+#[allow(clippy::must_use_candidate)]
+pub fn sanitize_with(data: &[u8], passthrough: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len());
+    for chunk in data.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let passed = u8::try_from(c).is_ok_and(|byte| passthrough.contains(&byte));
+            if !c.is_control() || passed {
+                text.push(c);
+            } else if c == '\x7f' {
+                text.push_str("^?");
+            } else if c < ' ' {
+                text.push('^');
+                text.push(char::from(c as u8 + b'@'));
+            } else {
+                let _ = write!(text, "\\u{{{:x}}}", u32::from(c));
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(text, "\\x{byte:02x}");
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_control_characters() {
+        assert_eq!(sanitize(b"a\x1bb\x00c\r\n"), "a^[b^@c\r\n");
+        assert_eq!(sanitize(b"\x7f\xff"), "^?\\xff");
+        assert_eq!(sanitize("é\u{85}".as_bytes()), "é\\u{85}");
+    }
+
+    #[test]
+    fn passes_through_configured_characters() {
+        assert_eq!(sanitize_with(b"\x07\t\n", b"\x07"), "\x07^I^J");
+    }
+}
@@ -0,0 +1,74 @@
+//! Character encodings for the text sent with [`Telnet::write_str`](crate::Telnet::write_str),
+//! and received with [`Telnet::decode_input`](crate::Telnet::decode_input).
+
+use crate::utf8;
+use std::{borrow::Cow, convert::TryFrom};
+
+/// Converts text to and from the bytes exchanged with the remote host.
+pub trait Encoding {
+    /// Encodes `text`. Characters which the encoding cannot represent are replaced.
+    fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]>;
+
+    /// Decodes `data`, received after `pending`. The bytes of a character cut at the end of
+    /// `data` are left in `pending`, to be completed by the next call. Invalid bytes are replaced.
+    ///
+    /// The default decodes UTF-8.
+    fn decode(&self, data: &[u8], pending: &mut Vec<u8>) -> String {
+        pending.extend_from_slice(data);
+        let tail = pending.split_off(pending.len() - utf8::incomplete_tail_len(pending));
+        let text = String::from_utf8_lossy(pending).into_owned();
+        *pending = tail;
+        text
+    }
+}
+
+/// UTF-8, the default encoding.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Utf8;
+
+impl Encoding for Utf8 {
+    fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]> {
+        Cow::Borrowed(text.as_bytes())
+    }
+}
+
+/// ISO-8859-1 (Latin-1), still used by many MUDs and older hosts. Characters above U+00FF are
+/// replaced with `?`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Latin1;
+
+impl Encoding for Latin1 {
+    fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]> {
+        if text.is_ascii() {
+            return Cow::Borrowed(text.as_bytes());
+        }
+        text.chars()
+            .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+            .collect()
+    }
+
+    fn decode(&self, data: &[u8], _pending: &mut Vec<u8>) -> String {
+        data.iter().map(|&byte| char::from(byte)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_latin1() {
+        assert_eq!(Latin1.encode("abc").as_ref(), b"abc");
+        assert_eq!(Latin1.encode("é€ÿ").as_ref(), [0xe9, b'?', 0xff]);
+    }
+
+    #[test]
+    fn decodes_utf8_across_calls() {
+        let mut pending = Vec::new();
+        // "é€" cut in the middle of both characters
+        assert_eq!(Utf8.decode(b"a\xc3", &mut pending), "a");
+        assert_eq!(Utf8.decode(b"\xa9\xe2\x82", &mut pending), "é");
+        assert_eq!(Utf8.decode(b"\xac", &mut pending), "€");
+        assert!(pending.is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+//! Decoding of the Encryption option (RFC 2946).
+//!
+//! Only the subnegotiation commands are decoded, so that a client can recognize an encryption
+//! request and refuse it with [`Telnet::refuse_encryption`](crate::Telnet::refuse_encryption).
+//! No cipher is implemented.
+
+const ENCRYPT_IS: u8 = 0;
+const ENCRYPT_SUPPORT: u8 = 1;
+const ENCRYPT_REPLY: u8 = 2;
+const ENCRYPT_START: u8 = 3;
+const ENCRYPT_END: u8 = 4;
+const ENCRYPT_REQUEST_START: u8 = 5;
+const ENCRYPT_REQUEST_END: u8 = 6;
+const ENCRYPT_ENC_KEYID: u8 = 7;
+const ENCRYPT_DEC_KEYID: u8 = 8;
+
+/// The encryption type which stands for no encryption
+pub const TYPE_NULL: u8 = 0;
+/// DES in 64 bit cipher feedback mode (RFC 2952)
+pub const TYPE_DES_CFB64: u8 = 1;
+/// DES in 64 bit output feedback mode (RFC 2953)
+pub const TYPE_DES_OFB64: u8 = 2;
+/// Triple DES in 64 bit cipher feedback mode (RFC 2947)
+pub const TYPE_DES3_CFB64: u8 = 3;
+/// Triple DES in 64 bit output feedback mode (RFC 2948)
+pub const TYPE_DES3_OFB64: u8 = 4;
+/// CAST-128 with a 40 bit key, in 64 bit cipher feedback mode (RFC 2950)
+pub const TYPE_CAST5_40_CFB64: u8 = 8;
+/// CAST-128 with a 40 bit key, in 64 bit output feedback mode (RFC 2949)
+pub const TYPE_CAST5_40_OFB64: u8 = 9;
+/// CAST-128 in 64 bit cipher feedback mode (RFC 2950)
+pub const TYPE_CAST128_CFB64: u8 = 10;
+/// CAST-128 in 64 bit output feedback mode (RFC 2949)
+pub const TYPE_CAST128_OFB64: u8 = 11;
+
+/// A command carried by an Encryption subnegotiation.
+///
+/// Encryption types are the bytes sent on the wire, see the `TYPE_*` constants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptCmd {
+    /// The encryption type chosen by the sender, with its data
+    Is(u8, Box<[u8]>),
+    /// The encryption types the sender supports, in order of preference
+    Support(Box<[u8]>),
+    /// The reply to an `IS`, with the data of the encryption type
+    Reply(u8, Box<[u8]>),
+    /// The data sent by the sender is encrypted from now on, with the given key ID
+    Start(Box<[u8]>),
+    /// The data sent by the sender is no longer encrypted
+    End,
+    /// A request to start encrypting, with the given key ID
+    RequestStart(Box<[u8]>),
+    /// A request to stop encrypting
+    RequestEnd,
+    /// The key ID the sender encrypts with
+    EncKeyId(Box<[u8]>),
+    /// The key ID the sender decrypts with
+    DecKeyId(Box<[u8]>),
+}
+
+/// Decodes the payload of an Encryption subnegotiation, or returns `None` if it is malformed.
+#[allow(clippy::must_use_candidate)]
+pub fn parse(data: &[u8]) -> Option<EncryptCmd> {
+    let (&cmd, rest) = data.split_first()?;
+    let typed = |rest: &[u8]| -> Option<(u8, Box<[u8]>)> {
+        let (&encrypt_type, data) = rest.split_first()?;
+        Some((encrypt_type, Box::from(data)))
+    };
+
+    let cmd = match cmd {
+        ENCRYPT_IS => {
+            let (encrypt_type, data) = typed(rest)?;
+            EncryptCmd::Is(encrypt_type, data)
+        }
+        ENCRYPT_SUPPORT => EncryptCmd::Support(Box::from(rest)),
+        ENCRYPT_REPLY => {
+            let (encrypt_type, data) = typed(rest)?;
+            EncryptCmd::Reply(encrypt_type, data)
+        }
+        ENCRYPT_START => EncryptCmd::Start(Box::from(rest)),
+        ENCRYPT_END if rest.is_empty() => EncryptCmd::End,
+        ENCRYPT_REQUEST_START => EncryptCmd::RequestStart(Box::from(rest)),
+        ENCRYPT_REQUEST_END if rest.is_empty() => EncryptCmd::RequestEnd,
+        ENCRYPT_ENC_KEYID => EncryptCmd::EncKeyId(Box::from(rest)),
+        ENCRYPT_DEC_KEYID => EncryptCmd::DecKeyId(Box::from(rest)),
+        _ => return None,
+    };
+    Some(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_encryption_commands() {
+        assert_eq!(
+            parse(&[1, TYPE_DES_CFB64, TYPE_CAST128_OFB64]),
+            Some(EncryptCmd::Support(Box::new([1, 11])))
+        );
+        assert_eq!(
+            parse(&[0, TYPE_DES_CFB64, 1, 2]),
+            Some(EncryptCmd::Is(TYPE_DES_CFB64, Box::new([1, 2])))
+        );
+        assert_eq!(parse(&[3, 0]), Some(EncryptCmd::Start(Box::new([0]))));
+        assert_eq!(parse(&[4]), Some(EncryptCmd::End));
+        assert_eq!(parse(&[6]), Some(EncryptCmd::RequestEnd));
+        assert_eq!(parse(&[2]), None);
+        assert_eq!(parse(&[4, 0]), None);
+        assert_eq!(parse(&[9]), None);
+        assert_eq!(parse(&[]), None);
+    }
+}
@@ -1,9 +1,19 @@
+use crate::{Action, TelnetOption};
+
 #[derive(Debug)]
 pub enum Error {
     UnexpectedByte(u8),
     InternalQueueErr,
+    QueueOverflow,
     NegotiationErr,
+    InvalidNegotiation(Action, TelnetOption),
     SubnegotiationErr(SubnegotiationType),
+    UnsolicitedSubnegotiation(TelnetOption),
+    IncompleteSubnegotiation(TelnetOption, Box<[u8]>),
+    MalformedSubnegotiation(TelnetOption, Box<[u8]>),
+    OptionNotEnabled(TelnetOption),
+    SubnegotiationTooLong(TelnetOption, usize),
+    BareSubnegotiationEnd(TelnetOption),
 }
 
 #[allow(clippy::enum_glob_use)]
@@ -15,7 +25,11 @@ impl std::fmt::Display for Error {
                 f.write_fmt(format_args!("Unexpected byte after IAC inside SB: {}", &b))
             }
             InternalQueueErr => f.write_str("Internal Queue Error"),
+            QueueOverflow => f.write_str("Event queue overflowed"),
             NegotiationErr => f.write_str("Negotiation failed"),
+            InvalidNegotiation(action, opt) => f.write_fmt(format_args!(
+                "Negotiation rejected in strict mode: {action:?} {opt:?}"
+            )),
             SubnegotiationErr(s) => {
                 use self::SubnegotiationType::*;
                 match s {
@@ -24,12 +38,38 @@ impl std::fmt::Display for Error {
                     End => f.write_str("Subnegotiation failed (END)"),
                 }
             }
+            UnsolicitedSubnegotiation(opt) => f.write_fmt(format_args!(
+                "Subnegotiation received for an option not agreed to: {opt:?}"
+            )),
+            IncompleteSubnegotiation(opt, data) => f.write_fmt(format_args!(
+                "Connection closed in the middle of a subnegotiation: {opt:?}, {} bytes received",
+                data.len()
+            )),
+            OptionNotEnabled(opt) => f.write_fmt(format_args!(
+                "Subnegotiation not sent for an option not agreed to: {opt:?}"
+            )),
+            SubnegotiationTooLong(opt, limit) => f.write_fmt(format_args!(
+                "Subnegotiation dropped for exceeding {limit} bytes: {opt:?}"
+            )),
+            BareSubnegotiationEnd(opt) => f.write_fmt(format_args!(
+                "Subnegotiation ended by SE without IAC: {opt:?}"
+            )),
+            MalformedSubnegotiation(opt, data) => f.write_fmt(format_args!(
+                "Subnegotiation aborted on an unexpected byte after IAC: {opt:?}, {} bytes received",
+                data.len()
+            )),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        std::io::Error::other(err)
+    }
+}
+
 #[derive(Debug)]
 pub enum SubnegotiationType {
     Start,
@@ -1,42 +1,267 @@
-use crate::{Action, TelnetError, TelnetOption};
-use std::collections::VecDeque;
+use crate::{
+    encryption::EncryptCmd, exopl::ExtendedOption, lflow::LFlowCmd, outmrk::OutputMarking,
+    rcte::RcteCmd, tn3270::RegimeCmd, Action, TelnetError, TelnetOption,
+};
+use std::{collections::VecDeque, fmt};
 
 /// Events generated by `Telnet`.
-#[derive(Debug)]
+///
+/// The `Debug` output shortens the payloads of [`Event::Data`], [`Event::RawCommand`],
+/// [`Event::RawFrame`] and [`Event::Subnegotiation`] to their first bytes and their length, to
+/// keep logs readable. Use the alternate form (`{:#?}`) to print the payloads in full.
 pub enum Event {
     /// Data received (excluding telnet commands)
     Data(Box<[u8]>),
     /// An unrecognized telnet command received
     UnknownIAC(u8),
+    /// An unrecognized telnet command received, with the bytes following it
+    RawCommand(Box<[u8]>),
+    /// The next negotiation or subnegotiation encoded again from what was parsed, from `IAC` to
+    /// `IAC SE`, when enabled with `Telnet::set_raw_frames`. Bytes the parser skipped or repaired
+    /// are not in it.
+    RawFrame(Box<[u8]>),
     /// A telnet negotiation received
     Negotiation(Action, TelnetOption),
+    /// The remote host agreed to a request of ours to enable an option, sent with `DO` or `WILL`.
+    /// Follows the [`Event::Negotiation`] of the reply.
+    OptionEnabled(TelnetOption),
+    /// The remote host refused a request of ours to enable an option, sent with `DO` or `WILL`.
+    /// Follows the [`Event::Negotiation`] of the reply.
+    OptionRefused(TelnetOption),
     /// A telnet subnegotiation data received
     Subnegotiation(TelnetOption, Box<[u8]>),
+    /// A decoded EXOPL subnegotiation
+    ExtendedOption(ExtendedOption),
+    /// A decoded LFLOW subnegotiation
+    LFlow(LFlowCmd),
+    /// A decoded TN3270-REGIME subnegotiation
+    Tn3270Regime(RegimeCmd),
+    /// A decoded RCTE subnegotiation
+    Rcte(RcteCmd),
+    /// A decoded OUTMRK subnegotiation
+    OutputMarking(OutputMarking),
+    /// A decoded Encryption subnegotiation
+    Encryption(EncryptCmd),
+    /// The terminal type of the remote host, from a TTYPE `IS` subnegotiation, when enabled with
+    /// `Telnet::set_decode_terminal_type`. Bytes which are not valid UTF-8 are replaced with
+    /// `U+FFFD`.
+    TerminalType(String),
+    /// A request for our terminal type, from a TTYPE `SEND` subnegotiation, when enabled with
+    /// `Telnet::set_decode_terminal_type`
+    TerminalTypeRequested,
     /// Read time out
     TimedOut,
+    /// No data received for the idle timeout set with `Telnet::set_idle_timeout`
+    Idle,
     /// No data to read
     NoData,
     /// Error encountered during processing read buffer
     Error(TelnetError),
 }
 
-#[repr(transparent)]
-pub struct TelnetEventQueue(VecDeque<Event>);
+/// An event passed to the callback of `Telnet::read_with`.
+///
+/// Data is borrowed from the read buffer of `Telnet`, so it is only valid during the call. The
+/// other events are passed as they are returned by `Telnet::read`.
+#[derive(Debug)]
+pub enum EventRef<'a> {
+    /// Data received (excluding telnet commands)
+    Data(&'a [u8]),
+    /// Any event other than [`Event::Data`]
+    Other(Event),
+}
+
+// How many bytes of a payload are printed by the `Debug` output of `Event`
+const DEBUG_PAYLOAD_LEN: usize = 16;
+
+// Prints a payload as `<length> bytes: [<first bytes>, ...]`, or `1 byte: [<byte>]`
+struct Payload<'a>(&'a [u8]);
+
+impl fmt::Debug for Payload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = if self.0.len() == 1 { "byte" } else { "bytes" };
+        write!(f, "{} {unit}: ", self.0.len())?;
+        if f.alternate() || self.0.len() <= DEBUG_PAYLOAD_LEN {
+            return write!(f, "{:?}", self.0);
+        }
+
+        f.write_str("[")?;
+        for byte in &self.0[..DEBUG_PAYLOAD_LEN] {
+            write!(f, "{byte}, ")?;
+        }
+        f.write_str("...]")
+    }
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Data(data) => f.debug_tuple("Data").field(&Payload(data)).finish(),
+            Event::UnknownIAC(byte) => f.debug_tuple("UnknownIAC").field(byte).finish(),
+            Event::RawCommand(command) => f
+                .debug_tuple("RawCommand")
+                .field(&Payload(command))
+                .finish(),
+            Event::RawFrame(frame) => f.debug_tuple("RawFrame").field(&Payload(frame)).finish(),
+            Event::Negotiation(action, opt) => f
+                .debug_tuple("Negotiation")
+                .field(action)
+                .field(opt)
+                .finish(),
+            Event::OptionEnabled(opt) => f.debug_tuple("OptionEnabled").field(opt).finish(),
+            Event::OptionRefused(opt) => f.debug_tuple("OptionRefused").field(opt).finish(),
+            Event::Subnegotiation(opt, data) => f
+                .debug_tuple("Subnegotiation")
+                .field(opt)
+                .field(&Payload(data))
+                .finish(),
+            Event::ExtendedOption(ext) => f.debug_tuple("ExtendedOption").field(ext).finish(),
+            Event::LFlow(cmd) => f.debug_tuple("LFlow").field(cmd).finish(),
+            Event::Tn3270Regime(cmd) => f.debug_tuple("Tn3270Regime").field(cmd).finish(),
+            Event::Rcte(cmd) => f.debug_tuple("Rcte").field(cmd).finish(),
+            Event::OutputMarking(marking) => f.debug_tuple("OutputMarking").field(marking).finish(),
+            Event::Encryption(cmd) => f.debug_tuple("Encryption").field(cmd).finish(),
+            Event::TerminalType(name) => f.debug_tuple("TerminalType").field(name).finish(),
+            Event::TerminalTypeRequested => f.write_str("TerminalTypeRequested"),
+            Event::TimedOut => f.write_str("TimedOut"),
+            Event::Idle => f.write_str("Idle"),
+            Event::NoData => f.write_str("NoData"),
+            Event::Error(err) => f.debug_tuple("Error").field(err).finish(),
+        }
+    }
+}
+
+/// What to do with new events once the event queue of `Telnet` is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOverflowPolicy {
+    /// Drop the oldest queued events to make room, replacing them with a single
+    /// `Event::Error(QueueOverflow)` at the front of the queue.
+    DropOldest,
+    /// Drop the new events, queuing a single `Event::Error(QueueOverflow)` after the queued ones.
+    DropNewest,
+}
+
+pub struct TelnetEventQueue {
+    events: VecDeque<Event>,
+    max_len: usize,
+    overflow_policy: QueueOverflowPolicy,
+}
 
 impl TelnetEventQueue {
     pub fn new() -> TelnetEventQueue {
-        TelnetEventQueue(VecDeque::new())
+        TelnetEventQueue::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> TelnetEventQueue {
+        TelnetEventQueue {
+            events: VecDeque::with_capacity(capacity),
+            max_len: usize::MAX,
+            overflow_policy: QueueOverflowPolicy::DropNewest,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.events.capacity()
+    }
+
+    pub fn set_max_len(&mut self, max_len: usize) {
+        // Leave room for the overflow error and at least one event
+        self.max_len = max_len.max(2);
+    }
+
+    pub fn set_overflow_policy(&mut self, policy: QueueOverflowPolicy) {
+        self.overflow_policy = policy;
     }
 
     pub fn push_event(&mut self, event: Event) {
-        self.0.push_back(event);
+        if self.events.len() < self.max_len {
+            self.events.push_back(event);
+            return;
+        }
+
+        match self.overflow_policy {
+            QueueOverflowPolicy::DropOldest => {
+                if self.events.front().is_some_and(is_overflow) {
+                    self.events.remove(1);
+                } else {
+                    self.events.pop_front();
+                    self.events.pop_front();
+                    self.events
+                        .push_front(Event::Error(TelnetError::QueueOverflow));
+                }
+                self.events.push_back(event);
+            }
+            QueueOverflowPolicy::DropNewest => {
+                if !self.events.back().is_some_and(is_overflow) {
+                    self.events
+                        .push_back(Event::Error(TelnetError::QueueOverflow));
+                }
+            }
+        }
+    }
+
+    // Put events back in front of the queue, in order. They were already queued once, so the
+    // limit does not apply to them.
+    pub fn requeue_front(&mut self, events: Vec<Event>) {
+        for event in events.into_iter().rev() {
+            self.events.push_front(event);
+        }
     }
 
     pub fn take_event(&mut self) -> Option<Event> {
-        self.0.pop_front()
+        self.events.pop_front()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.events.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+}
+
+fn is_overflow(event: &Event) -> bool {
+    matches!(event, Event::Error(TelnetError::QueueOverflow))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortens_payloads_in_debug_output() {
+        let data: Vec<u8> = (0..=255).cycle().take(1024).collect();
+        let event = Event::Data(data.into_boxed_slice());
+        assert_eq!(
+            format!("{event:?}"),
+            "Data(1024 bytes: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, ...])"
+        );
+
+        let event = Event::Subnegotiation(TelnetOption::TTYPE, Box::new([1]));
+        assert_eq!(format!("{event:?}"), "Subnegotiation(TTYPE, 1 byte: [1])");
+    }
+
+    #[test]
+    fn requeues_events_beyond_the_limit() {
+        let mut queue = TelnetEventQueue::new();
+        queue.set_max_len(2);
+        queue.set_overflow_policy(QueueOverflowPolicy::DropOldest);
+        queue.push_event(Event::NoData);
+        queue.push_event(Event::Idle);
+
+        queue.requeue_front(vec![Event::TimedOut, Event::Data(Box::new([1]))]);
+        assert_eq!(queue.len(), 4);
+        assert!(matches!(queue.take_event(), Some(Event::TimedOut)));
+        assert!(matches!(queue.take_event(), Some(Event::Data(_))));
+        assert!(matches!(queue.take_event(), Some(Event::NoData)));
+        assert!(matches!(queue.take_event(), Some(Event::Idle)));
+    }
+
+    #[test]
+    fn prints_full_payloads_in_alternate_debug_output() {
+        let event = Event::Data(vec![7; 20].into_boxed_slice());
+        assert!(format!("{event:#?}").contains(&format!("20 bytes: {:?}", [7; 20])));
     }
 }
@@ -0,0 +1,54 @@
+//! Decoding of the Extended Options List option (EXOPL, RFC 861).
+//!
+//! Options beyond the 256 standard ones are negotiated inside an EXOPL subnegotiation, which
+//! carries a negotiation command or a nested subnegotiation for an extended option code. The
+//! extended option code is kept as the raw byte found on the wire.
+
+use crate::{
+    byte::{BYTE_SB, BYTE_SE},
+    Action,
+};
+
+/// A decoded EXOPL subnegotiation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtendedOption {
+    /// `IAC SB EXOPL <action> <code> IAC SE`
+    Negotiation(Action, u8),
+    /// `IAC SB EXOPL SB <code> <data> SE IAC SE`
+    Subnegotiation(u8, Box<[u8]>),
+}
+
+/// Decodes the payload of an EXOPL subnegotiation, or returns `None` if it is malformed.
+#[allow(clippy::must_use_candidate)]
+pub fn parse(data: &[u8]) -> Option<ExtendedOption> {
+    match data {
+        [BYTE_SB, code, rest @ ..] => {
+            let rest = rest.strip_suffix(&[BYTE_SE]).unwrap_or(rest);
+            Some(ExtendedOption::Subnegotiation(*code, Box::from(rest)))
+        }
+        [cmd, code] => Action::parse(*cmd).map(|action| ExtendedOption::Negotiation(action, *code)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_negotiation() {
+        assert_eq!(
+            parse(&[253, 7]),
+            Some(ExtendedOption::Negotiation(Action::Do, 7))
+        );
+        assert_eq!(parse(&[1, 7]), None);
+    }
+
+    #[test]
+    fn parses_subnegotiation() {
+        assert_eq!(
+            parse(&[250, 3, 1, 2, 240]),
+            Some(ExtendedOption::Subnegotiation(3, Box::new([1, 2])))
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Helpers for building the raw bytes of telnet frames.
+
+#![allow(clippy::must_use_candidate)]
+
+use crate::{
+    byte::{BYTE_IAC, BYTE_SB, BYTE_SE},
+    Action, TelnetOption,
+};
+
+/// Escapes the data by doubling every IAC byte.
+pub fn data(data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(data.len());
+    escape_to(data, &mut buf);
+    buf
+}
+
+/// Returns the length of `data` once escaped, which is its length plus the number of IAC bytes.
+#[allow(clippy::naive_bytecount)]
+pub fn escaped_len(data: &[u8]) -> usize {
+    data.len() + data.iter().filter(|&&byte| byte == BYTE_IAC).count()
+}
+
+/// Escapes `data` into `buf` without allocating, and returns the number of bytes written.
+///
+/// Use [`escaped_len`] to size `buf`. If `buf` is too short, only the bytes of `data` which fit
+/// are escaped; an IAC byte is never split from its escape.
+pub fn escape_into(data: &[u8], buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    for &byte in data {
+        let size = if byte == BYTE_IAC { 2 } else { 1 };
+        let Some(dst) = buf.get_mut(len..len + size) else {
+            break;
+        };
+        dst.fill(byte);
+        len += size;
+    }
+    len
+}
+
+/// Builds a negotiation frame: `IAC <action> <option>`.
+///
+/// # Examples
+/// ```rust
+/// use telnet::{format, Action, TelnetOption};
+///
+/// assert_eq!(format::negotiation(Action::Do, TelnetOption::NAWS), [255, 253, 31]);
+/// ```
+pub fn negotiation(action: Action, opt: TelnetOption) -> [u8; 3] {
+    [BYTE_IAC, action.as_byte(), opt.as_byte()]
+}
+
+/// Builds a complete subnegotiation frame: `IAC SB <option> <escaped data> IAC SE`.
+pub fn sub_negotiation(opt: TelnetOption, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(data.len() + 5);
+    buf.extend_from_slice(&[BYTE_IAC, BYTE_SB, opt.as_byte()]);
+    escape_to(data, &mut buf);
+    buf.extend_from_slice(&[BYTE_IAC, BYTE_SE]);
+    buf
+}
+
+/// Builds a subnegotiation frame from several parts.
+///
+/// The payload is given as raw bytes, and escaped by [`SubnegotiationBuilder::build`]. This eases
+/// building payloads with several sub-commands, such as a CHARSET `REQUEST` with several names.
+///
+/// # Examples
+/// ```rust
+/// use telnet::{format::SubnegotiationBuilder, TelnetOption};
+///
+/// // CHARSET REQUEST ";UTF-8;US-ASCII"
+/// let frame = SubnegotiationBuilder::new(TelnetOption::UnknownOption(42))
+///     .byte(1)
+///     .sep(b';')
+///     .bytes(b"UTF-8")
+///     .sep(b';')
+///     .bytes(b"US-ASCII")
+///     .build();
+/// assert_eq!(frame, b"\xff\xfa\x2a\x01;UTF-8;US-ASCII\xff\xf0");
+/// ```
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct SubnegotiationBuilder {
+    opt: TelnetOption,
+    payload: Vec<u8>,
+}
+
+impl SubnegotiationBuilder {
+    /// Starts a subnegotiation for `opt`, with an empty payload.
+    pub fn new(opt: TelnetOption) -> Self {
+        SubnegotiationBuilder {
+            opt,
+            payload: Vec::new(),
+        }
+    }
+
+    /// Appends a byte, such as a sub-command code.
+    pub fn byte(mut self, byte: u8) -> Self {
+        self.payload.push(byte);
+        self
+    }
+
+    /// Appends bytes, such as a name or a value.
+    pub fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.payload.extend_from_slice(bytes);
+        self
+    }
+
+    /// Appends a separator byte. This is the same as [`SubnegotiationBuilder::byte`], and reads
+    /// better between the items of a list.
+    pub fn sep(self, separator: u8) -> Self {
+        self.byte(separator)
+    }
+
+    /// Builds the complete frame, `IAC SB <option> <escaped payload> IAC SE`.
+    pub fn build(&self) -> Vec<u8> {
+        sub_negotiation(self.opt, &self.payload)
+    }
+}
+
+pub(crate) fn escape_to(data: &[u8], buf: &mut Vec<u8>) {
+    for &byte in data {
+        buf.push(byte);
+        if byte == BYTE_IAC {
+            buf.push(BYTE_IAC);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_negotiation_with_public_action() {
+        assert_eq!(
+            negotiation(Action::Will, TelnetOption::Echo),
+            [0xff, 0xfb, 0x01]
+        );
+        assert_eq!(
+            negotiation(Action::Dont, TelnetOption::UnknownOption(200)),
+            [0xff, 0xfe, 200]
+        );
+    }
+
+    #[test]
+    fn escaped_len_matches_escape_into() {
+        for input in [&b""[..], b"plain text", &[0xff, b'a', 0xff], &[0xff; 64]] {
+            let mut buf = vec![0; escaped_len(input)];
+            assert_eq!(escape_into(input, &mut buf), buf.len());
+            assert_eq!(buf, data(input));
+        }
+    }
+
+    #[test]
+    fn escape_into_stops_at_end_of_buffer() {
+        let mut buf = [0; 4];
+        assert_eq!(escape_into(&[b'a', 0xff, 0xff], &mut buf), 3);
+        assert_eq!(buf[..3], [b'a', 0xff, 0xff]);
+    }
+
+    #[test]
+    fn builds_charset_request() {
+        let frame = SubnegotiationBuilder::new(TelnetOption::UnknownOption(42))
+            .byte(1)
+            .sep(b';')
+            .bytes(b"UTF-8")
+            .sep(b';')
+            .bytes(b"ISO-8859-1")
+            .build();
+        let mut expected = vec![0xff, 0xfa, 42, 1];
+        expected.extend_from_slice(b";UTF-8;ISO-8859-1");
+        expected.extend_from_slice(&[0xff, 0xf0]);
+        assert_eq!(frame, expected);
+    }
+
+    #[test]
+    fn builds_new_environ_frame_with_escaping() {
+        // IS VAR "USER" VALUE "bob" USERVAR "X" VALUE 0xff
+        let frame = SubnegotiationBuilder::new(TelnetOption::NewEnvironment)
+            .byte(0)
+            .byte(0)
+            .bytes(b"USER")
+            .byte(1)
+            .bytes(b"bob")
+            .byte(3)
+            .bytes(b"X")
+            .byte(1)
+            .byte(0xff)
+            .build();
+        let mut expected = vec![0xff, 0xfa, 39, 0, 0];
+        expected.extend_from_slice(b"USER\x01bob\x03X\x01");
+        expected.extend_from_slice(&[0xff, 0xff, 0xff, 0xf0]);
+        assert_eq!(frame, expected);
+    }
+}
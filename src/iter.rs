@@ -0,0 +1,39 @@
+use crate::{Event, Telnet};
+use std::{io, time::Instant};
+
+/// An iterator over the events read before a deadline. See [`Telnet::events_until`].
+pub struct EventsUntil<'a> {
+    telnet: &'a mut Telnet,
+    deadline: Instant,
+    done: bool,
+}
+
+impl<'a> EventsUntil<'a> {
+    pub fn new(telnet: &'a mut Telnet, deadline: Instant) -> EventsUntil<'a> {
+        EventsUntil {
+            telnet,
+            deadline,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for EventsUntil<'_> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        let result = if remaining.is_zero() {
+            Ok(Event::TimedOut)
+        } else {
+            self.telnet.read_timeout(remaining)
+        };
+
+        self.done = matches!(result, Ok(Event::TimedOut) | Err(_));
+        Some(result)
+    }
+}
@@ -0,0 +1,62 @@
+//! Decoding of the Remote Flow Control option (LFLOW, RFC 1372).
+
+/// A command carried by an LFLOW subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LFlowCmd {
+    /// Disable remote flow control
+    Off,
+    /// Enable remote flow control
+    On,
+    /// Any character restarts the output
+    RestartAny,
+    /// Only XON restarts the output
+    RestartXon,
+    /// An unrecognized command
+    Unknown(u8),
+}
+
+impl LFlowCmd {
+    #[allow(clippy::must_use_candidate)]
+    pub fn parse(byte: u8) -> LFlowCmd {
+        match byte {
+            0 => LFlowCmd::Off,
+            1 => LFlowCmd::On,
+            2 => LFlowCmd::RestartAny,
+            3 => LFlowCmd::RestartXon,
+            byte => LFlowCmd::Unknown(byte),
+        }
+    }
+
+    #[allow(clippy::must_use_candidate)]
+    pub fn as_byte(&self) -> u8 {
+        match *self {
+            LFlowCmd::Off => 0,
+            LFlowCmd::On => 1,
+            LFlowCmd::RestartAny => 2,
+            LFlowCmd::RestartXon => 3,
+            LFlowCmd::Unknown(byte) => byte,
+        }
+    }
+}
+
+/// Decodes the payload of an LFLOW subnegotiation, or returns `None` if it is malformed.
+#[allow(clippy::must_use_candidate)]
+pub fn parse(data: &[u8]) -> Option<LFlowCmd> {
+    match data {
+        [cmd] => Some(LFlowCmd::parse(*cmd)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse(&[1]), Some(LFlowCmd::On));
+        assert_eq!(parse(&[3]), Some(LFlowCmd::RestartXon));
+        assert_eq!(parse(&[9]), Some(LFlowCmd::Unknown(9)));
+        assert_eq!(parse(&[]), None);
+    }
+}
@@ -21,22 +21,48 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::upper_case_acronyms)]
 
+pub mod auth;
+mod backoff;
+mod bufstream;
 mod byte;
+mod connect;
+mod cr;
+mod deadline;
+pub mod display;
+pub mod encoding;
+pub mod encryption;
 mod error;
 mod event;
+pub mod exopl;
+pub mod format;
+mod iter;
+pub mod lflow;
+mod neglog;
 mod negotiation;
 mod option;
+pub mod outmrk;
+pub mod rcte;
+mod reader;
 mod stream;
+pub mod subneg;
+pub mod tn3270;
+mod utf8;
 #[cfg(feature = "zcstream")]
 mod zcstream;
 #[cfg(feature = "zcstream")]
 mod zlibstream;
 
 // Re-exports
+pub use backoff::Backoff;
+pub use connect::ConnectOptions;
+pub use cr::BareCrPolicy;
+pub use deadline::Deadline;
 pub use error::{Error as TelnetError, SubnegotiationType};
-pub use event::Event;
+pub use event::{Event, EventRef, QueueOverflowPolicy};
+pub use neglog::{Direction, LoggedNegotiation};
 pub use negotiation::Action;
-pub use option::TelnetOption;
+pub use option::{opt, TelnetOption};
+pub use reader::DataReader;
 pub use stream::Stream;
 #[cfg(feature = "zcstream")]
 pub use zcstream::ZCStream;
@@ -48,30 +74,58 @@ use byte::*;
 #[allow(clippy::enum_glob_use)]
 use error::Error::*;
 use event::TelnetEventQueue;
+use neglog::NegotiationLog;
+use negotiation::OptionStates;
 use std::{
+    collections::{BTreeMap, VecDeque},
     io::{self, ErrorKind, Read, Write},
-    net::{SocketAddr, TcpStream, ToSocketAddrs},
-    time::Duration,
+    net::{SocketAddr, SocketAddrV6, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
 };
 
+// The size of the read buffer when none is given
+const DEFAULT_BUF_SIZE: usize = 4096;
+
+// How many bytes without any IAC make `set_auto_detect` conclude the stream is not telnet
+const AUTO_DETECT_LEN: usize = 256;
+
+type UnknownIacHandler = dyn FnMut(u8, &mut Telnet);
+
 #[cfg(feature = "zcstream")]
 type TStream = dyn zcstream::ZCStream;
 #[cfg(not(feature = "zcstream"))]
 type TStream = dyn stream::Stream;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum ProcessState {
     NormalData,
     IAC,
     SB,
-    SBData(TelnetOption, usize), // (option, start location of option data)
-    SBDataIAC(TelnetOption, usize), // (option, start location of option data)
+    SBData(TelnetOption),
+    SBDataIAC(TelnetOption),
+    SBDiscard,
+    SBDiscardIAC,
+    RawCommand,
+    CommandParams,
     Will,
     Wont,
     Do,
     Dont,
 }
 
+impl ProcessState {
+    // The negotiation action being parsed, if any
+    fn action(self) -> Option<Action> {
+        match self {
+            ProcessState::Will => Some(Action::Will),
+            ProcessState::Wont => Some(Action::Wont),
+            ProcessState::Do => Some(Action::Do),
+            ProcessState::Dont => Some(Action::Dont),
+            _ => None,
+        }
+    }
+}
+
 /// A telnet connection to a remote host.
 ///
 /// # Examples
@@ -85,15 +139,77 @@ enum ProcessState {
 ///     println!("{:?}", event);
 /// }
 /// ```
+#[allow(clippy::struct_excessive_bools)]
 pub struct Telnet {
     stream: Box<TStream>,
     event_queue: TelnetEventQueue,
+    options: OptionStates,
+    negotiation_log: NegotiationLog,
+    window_size: Option<(u16, u16)>,
+    subnegotiation_policy: subneg::SubnegotiationPolicy,
+    deferred_subnegotiations: Vec<(TelnetOption, Box<[u8]>)>,
+    input_encoding: Box<dyn encoding::Encoding>,
+    input_pending: Vec<u8>,
+    output_encoding: Box<dyn encoding::Encoding>,
 
     // Buffer
     buffer: Box<[u8]>,
     buffered_size: usize,
-    process_buffer: Box<[u8]>,
-    process_buffered_size: usize,
+
+    // Parser state, kept across reads
+    state: ProcessState,
+    sb_buffer: Vec<u8>,
+    max_subnegotiation_len: Option<usize>,
+    subnegotiation_limits: BTreeMap<u8, usize>,
+    raw_commands: bool,
+    unknown_iac_handler: Option<Box<UnknownIacHandler>>,
+    // The parameter bytes left to collect after an unknown command
+    command_params: usize,
+    raw_frames: bool,
+    decode_terminal_type: bool,
+    strict_subnegotiation: bool,
+    strict_negotiation: bool,
+    lenient_se_after: Option<usize>,
+    // Every byte is data, after SUPDUP or a raw stream was detected
+    passthrough: bool,
+    auto_detect: bool,
+    auto_detect_seen: usize,
+    negotiation_transparent: bool,
+    auto_respond: Option<Vec<TelnetOption>>,
+    errors_as_io_error: bool,
+    #[cfg(feature = "zcstream")]
+    auto_mccp: bool,
+    allow_unsolicited_subnegotiation: bool,
+
+    // UTF-8 reassembly
+    utf8_reassembly: bool,
+    utf8_pending: Vec<u8>,
+    bare_cr_policy: BareCrPolicy,
+    cr_pending: bool,
+    max_write_chunk: Option<usize>,
+    corked: bool,
+    // Writes held back by `cork` when the stream cannot cork itself
+    cork_buffer: Option<Vec<u8>>,
+    // While `read_with` processes a read, the data left in the buffer, with the number of events
+    // queued before each range
+    borrowed_data: Option<Vec<(usize, std::ops::Range<usize>)>>,
+    // Bytes which the stream did not take yet, written before anything else
+    outbound: Vec<u8>,
+
+    // Data coalescing
+    coalesce: Option<(usize, Duration)>,
+    coalesced: Vec<u8>,
+    coalesce_deadline: Option<Instant>,
+
+    // Idle detection
+    idle_timeout: Option<Duration>,
+    default_read_timeout: Option<Duration>,
+    // The mode last set on the stream, to skip setting it again. `None` until set.
+    stream_nonblocking: Option<bool>,
+    #[allow(clippy::option_option)]
+    stream_read_timeout: Option<Option<Duration>>,
+    last_data: Instant,
+    last_activity: Instant,
 }
 
 #[allow(clippy::must_use_candidate)]
@@ -102,7 +218,8 @@ impl Telnet {
     ///
     /// `addr` is an address of the remote host. Note that a remote host usually opens port 23 for
     /// a Telnet connection. `buf_size` is a size of the underlying buffer for processing the data
-    ///  read from the remote host.
+    ///  read from the remote host. This is the only buffer of that size a connection keeps, even
+    /// for data full of escaped IAC bytes, so 4096 bytes cost 4 KiB per connection.
     ///
     /// # Examples
     /// ```rust,should_panic
@@ -117,15 +234,220 @@ impl Telnet {
     pub fn connect<A: ToSocketAddrs>(addr: A, buf_size: usize) -> io::Result<Telnet> {
         let stream = TcpStream::connect(addr)?; // send the error out directly
 
-        #[cfg(feature = "zcstream")]
-        return Ok(Telnet::from_stream(
-            Box::new(ZlibStream::from_stream(stream)),
-            buf_size,
-        ));
-        #[cfg(not(feature = "zcstream"))]
-        return Ok(Telnet::from_stream(Box::new(stream), buf_size));
+        Ok(Telnet::from_tcp_stream(stream, buf_size))
+    }
+
+    /// Opens a telnet connection to a remote host, wrapping the connected [`TcpStream`] with
+    /// `wrap` before handing it to [`Telnet::from_stream`].
+    ///
+    /// This inserts a stream decorator, for logging, throttling or metrics, between the socket
+    /// and the telnet parsing. With the `zcstream` feature, `wrap` must return a [`ZCStream`]:
+    /// wrap the decorated stream in a [`ZlibStream`] to keep MCCP support, the decorator then
+    /// seeing the compressed bytes.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::Telnet;
+    ///
+    /// let connection = Telnet::connect_with(("127.0.0.1", 23), 256, |stream| {
+    ///     // Wrap `stream` in a decorator here
+    ///     #[cfg(feature = "zcstream")]
+    ///     return Box::new(telnet::ZlibStream::from_stream(stream));
+    ///     #[cfg(not(feature = "zcstream"))]
+    ///     return Box::new(stream);
+    /// })
+    /// .expect("Couldn't connect to the server...");
+    /// ```
+    ///
+    /// # Errors
+    /// - Tcp connection failure
+    pub fn connect_with<A, F>(addr: A, buf_size: usize, wrap: F) -> io::Result<Telnet>
+    where
+        A: ToSocketAddrs,
+        F: FnOnce(TcpStream) -> Box<TStream>,
+    {
+        let stream = TcpStream::connect(addr)?;
+
+        Ok(Telnet::from_stream(wrap(stream), buf_size))
+    }
+
+    /// Opens a telnet connection to a remote host, with the settings of `options`.
+    ///
+    /// The socket options are set before the first negotiation is sent. With a connect timeout,
+    /// every address of `addr` is tried in turn, as with [`TcpStream::connect`].
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use std::time::Duration;
+    /// use telnet::{ConnectOptions, Telnet};
+    ///
+    /// let options = ConnectOptions {
+    ///     connect_timeout: Some(Duration::from_secs(2)),
+    ///     nodelay: true,
+    ///     ..ConnectOptions::default()
+    /// };
+    /// let connection = Telnet::connect_with_options(("127.0.0.1", 23), options)
+    ///         .expect("Couldn't connect to the server...");
+    /// ```
+    ///
+    /// # Errors
+    /// - Tcp connection failure
+    /// - I/O timeout error
+    /// - Setting a socket option fails
+    /// - Sending a negotiation fails
+    pub fn connect_with_options<A: ToSocketAddrs>(
+        addr: A,
+        options: ConnectOptions,
+    ) -> io::Result<Telnet> {
+        let stream = match options.connect_timeout {
+            Some(timeout) => {
+                let mut last_err =
+                    io::Error::new(ErrorKind::InvalidInput, "no address to connect to");
+                let mut connected = None;
+                for addr in addr.to_socket_addrs()? {
+                    match TcpStream::connect_timeout(&addr, timeout) {
+                        Ok(stream) => {
+                            connected = Some(stream);
+                            break;
+                        }
+                        Err(e) => last_err = e,
+                    }
+                }
+                connected.ok_or(last_err)?
+            }
+            None => TcpStream::connect(addr)?,
+        };
+        options.apply(&stream)?;
+
+        let mut telnet = Telnet::from_tcp_stream(stream, options.buf_size);
+        telnet.set_read_timeout(options.read_timeout);
+        for (action, opt) in options.initial_negotiations {
+            telnet.negotiate(&action, opt)?;
+        }
+        Ok(telnet)
+    }
+
+    /// Opens a telnet connection to `host`, resolving it with `resolver` instead of the system
+    /// resolver.
+    ///
+    /// This lets the caller control name resolution, with a custom resolver, a cache or DNS over
+    /// HTTPS. `resolver` is called once with `host`, and the returned addresses are tried in turn.
+    /// Their port is replaced by `port`.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use std::net::{Ipv4Addr, SocketAddr};
+    /// use telnet::Telnet;
+    ///
+    /// let connection = Telnet::connect_with_resolver("bbs.example", 23, 256, |_host| {
+    ///     Ok(vec![SocketAddr::from((Ipv4Addr::LOCALHOST, 0))])
+    /// })
+    /// .expect("Couldn't connect to the server...");
+    /// ```
+    ///
+    /// # Errors
+    /// - Any error of `resolver`
+    /// - Tcp connection failure, including when `resolver` returns no address
+    pub fn connect_with_resolver<R>(
+        host: &str,
+        port: u16,
+        buf_size: usize,
+        resolver: R,
+    ) -> io::Result<Telnet>
+    where
+        R: FnOnce(&str) -> io::Result<Vec<SocketAddr>>,
+    {
+        let mut addrs = resolver(host)?;
+        for addr in &mut addrs {
+            addr.set_port(port);
+        }
+        Telnet::connect(&addrs[..], buf_size)
     }
-    /// Opens a telnet connection to a remote host using a TcpStream with a timeout [`Duration`]. Uses a [`TcpStream::connect_timeout`] under the hood
+
+    /// Opens a telnet connection to a remote host, letting `configure` set socket options first.
+    ///
+    /// `configure` is called on each freshly created socket, before it connects. It can set any
+    /// option supported by [`socket2::Socket`], like `SO_REUSEADDR`, the buffer sizes or the
+    /// TOS.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::Telnet;
+    ///
+    /// let connection = Telnet::connect_configured(("127.0.0.1", 23), 256, |socket| {
+    ///     socket.set_recv_buffer_size(64 * 1024)
+    /// })
+    /// .expect("Couldn't connect to the server...");
+    /// ```
+    ///
+    /// # Errors
+    /// - Any error of `configure`
+    /// - Socket creation or Tcp connection failure
+    pub fn connect_configured<A, F>(
+        addr: A,
+        buf_size: usize,
+        mut configure: F,
+    ) -> io::Result<Telnet>
+    where
+        A: ToSocketAddrs,
+        F: FnMut(&socket2::Socket) -> io::Result<()>,
+    {
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        let mut last_err = io::Error::new(ErrorKind::InvalidInput, "no address to connect to");
+        for addr in addr.to_socket_addrs()? {
+            let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+            configure(&socket)?;
+            match socket.connect(&addr.into()) {
+                Ok(()) => return Ok(Telnet::from_tcp_stream(socket.into(), buf_size)),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Opens a telnet connection to a remote host, trying up to `attempts` times.
+    ///
+    /// This is useful for a server which may be briefly unavailable, for example while it
+    /// restarts. Between attempts, it waits as long as given by `backoff`. If every attempt fails,
+    /// the error of the last one is returned.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use std::time::Duration;
+    /// use telnet::{Backoff, Telnet};
+    ///
+    /// let backoff = Backoff::Exponential {
+    ///     initial: Duration::from_millis(100),
+    ///     max: Duration::from_secs(2),
+    /// };
+    /// let connection = Telnet::connect_retry(("127.0.0.1", 23), 256, 5, backoff)
+    ///         .expect("Couldn't connect to the server...");
+    /// ```
+    ///
+    /// # Errors
+    /// - Tcp connection failure on the last attempt
+    /// - [`ErrorKind::InvalidInput`] if `attempts` is 0
+    pub fn connect_retry<A: ToSocketAddrs>(
+        addr: A,
+        buf_size: usize,
+        attempts: u32,
+        backoff: Backoff,
+    ) -> io::Result<Telnet> {
+        let mut last_err = io::Error::new(ErrorKind::InvalidInput, "no connection attempt made");
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                std::thread::sleep(backoff.delay(attempt - 1));
+            }
+            match TcpStream::connect(&addr) {
+                Ok(stream) => return Ok(Telnet::from_tcp_stream(stream, buf_size)),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Opens a telnet connection to a remote host using a `TcpStream` with a timeout [`Duration`]. Uses a [`TcpStream::connect_timeout`] under the hood
     /// and so can only be passed a single address of type [`SocketAddr`], and passing a zero [`Duration`] results in an error.
     /// # Examples
     /// ```rust,should_panic
@@ -149,13 +471,142 @@ impl Telnet {
     ) -> io::Result<Telnet> {
         let stream = TcpStream::connect_timeout(addr, timeout)?; // send the error out directly
 
+        Ok(Telnet::from_tcp_stream(stream, buf_size))
+    }
+
+    /// Opens a telnet connection to an IPv6 host with a timeout [`Duration`], keeping the scope id
+    /// and flow info of the address.
+    ///
+    /// This is the same as [`Telnet::connect_timeout`], for a link-local address such as
+    /// `fe80::1%eth0`, whose scope id selects the network interface to connect through.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use std::net::{Ipv6Addr, SocketAddrV6};
+    /// use std::time::Duration;
+    /// use telnet::Telnet;
+    ///
+    /// // fe80::1 on the interface with index 2
+    /// let address = SocketAddrV6::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1), 23, 0, 2);
+    /// let telnet = Telnet::connect_timeout_v6(&address, 256, Duration::from_secs(2))
+    ///         .expect("Couldn't connect to the server...");
+    /// ```
+    ///
+    /// # Errors
+    /// - Tcp connection failure
+    /// - I/O timeout error
+    pub fn connect_timeout_v6(
+        addr: &SocketAddrV6,
+        buf_size: usize,
+        timeout: Duration,
+    ) -> io::Result<Telnet> {
+        Telnet::connect_timeout(&SocketAddr::V6(*addr), buf_size, timeout)
+    }
+
+    /// Opens a telnet connection over an already connected [`TcpStream`].
+    ///
+    /// This is useful when the stream comes from a pool, or was set up with specific socket
+    /// options before being handed over. With the `zcstream` feature, the stream is wrapped in a
+    /// [`ZlibStream`] the same way as with [`Telnet::connect`].
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use std::net::TcpStream;
+    /// use telnet::Telnet;
+    ///
+    /// let stream = TcpStream::connect(("127.0.0.1", 23))
+    ///         .expect("Couldn't connect to the server...");
+    /// stream.set_nodelay(true).expect("Couldn't set TCP_NODELAY");
+    /// let connection = Telnet::from_tcp_stream(stream, 256);
+    /// ```
+    pub fn from_tcp_stream(stream: TcpStream, buf_size: usize) -> Telnet {
         #[cfg(feature = "zcstream")]
-        return Ok(Telnet::from_stream(
-            Box::new(ZlibStream::from_stream(stream)),
-            buf_size,
-        ));
+        return Telnet::from_stream(Box::new(ZlibStream::from_stream(stream)), buf_size);
         #[cfg(not(feature = "zcstream"))]
-        return Ok(Telnet::from_stream(Box::new(stream), buf_size));
+        return Telnet::from_stream(Box::new(stream), buf_size);
+    }
+
+    /// Opens a telnet connection and waits for the login prompt of the remote host.
+    ///
+    /// Any negotiation requested by the remote host is agreed to if its option is listed in
+    /// `opts`, and refused otherwise. Reading stops at the first non-empty [`Event::Data`], which is
+    /// usually the login banner or prompt, and its content is returned together with the connection.
+    /// Any other event read on the way is dropped.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::{Telnet, TelnetOption};
+    ///
+    /// let (connection, prompt) = Telnet::connect_and_login(("127.0.0.1", 23), 256, &[TelnetOption::Echo])
+    ///         .expect("Couldn't connect to the server...");
+    /// println!("{}", String::from_utf8_lossy(&prompt));
+    /// ```
+    ///
+    /// # Errors
+    /// - Tcp connection failure
+    /// - Read or write stream fails
+    pub fn connect_and_login<A: ToSocketAddrs>(
+        addr: A,
+        buf_size: usize,
+        opts: &[TelnetOption],
+    ) -> io::Result<(Telnet, Box<[u8]>)> {
+        let mut telnet = Telnet::connect(addr, buf_size)?;
+
+        loop {
+            match telnet.read()? {
+                Event::Data(data) if !data.is_empty() => return Ok((telnet, data)),
+                Event::Negotiation(action, opt) => {
+                    let reply = if opts.contains(&opt) {
+                        action.accept()
+                    } else {
+                        action.refuse()
+                    };
+                    if let Some(reply) = reply {
+                        telnet.negotiate(&reply, opt)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Opens a telnet connection and returns the first batch of events sent by the remote host.
+    ///
+    /// A single read is made, waiting at most `initial_timeout`, and every event it produced is
+    /// returned. This is typically the burst of negotiations a server sends on accept, which the
+    /// caller can then answer before anything else. The list is empty if nothing arrived in time.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use std::time::Duration;
+    /// use telnet::Telnet;
+    ///
+    /// let (connection, events) =
+    ///     Telnet::connect_and_drain(("127.0.0.1", 23), 256, Duration::from_secs(1))
+    ///         .expect("Couldn't connect to the server...");
+    /// println!("{:?}", events);
+    /// ```
+    ///
+    /// # Errors
+    /// - Tcp connection failure
+    /// - Read stream fails
+    pub fn connect_and_drain<A: ToSocketAddrs>(
+        addr: A,
+        buf_size: usize,
+        initial_timeout: Duration,
+    ) -> io::Result<(Telnet, Vec<Event>)> {
+        let mut telnet = Telnet::connect(addr, buf_size)?;
+
+        let mut events = Vec::new();
+        match telnet.read_timeout(initial_timeout)? {
+            Event::TimedOut => {}
+            event => events.push(event),
+        }
+        while let Some(event) = telnet.event_queue.take_event() {
+            events.push(event);
+        }
+
+        Ok((telnet, events))
     }
 
     #[cfg(feature = "zcstream")]
@@ -163,11 +614,42 @@ impl Telnet {
         self.stream.begin_zlib();
     }
 
+    /// Begins zlib decompression using a preset dictionary, for MCCP2 servers which compress
+    /// with one.
+    ///
+    /// [`ZlibStream`] supports it with the `zlib-dictionary` feature, which uses the zlib-rs
+    /// backend of flate2.
+    ///
+    /// # Errors
+    /// - [`ErrorKind::Unsupported`] if the stream does not support preset dictionaries, such as
+    ///   [`ZlibStream`] without the `zlib-dictionary` feature. Decompression does not begin.
+    #[cfg(feature = "zcstream")]
+    pub fn begin_zlib_with_dictionary(&mut self, dictionary: &[u8]) -> io::Result<()> {
+        self.stream.begin_zlib_with_dictionary(dictionary)
+    }
+
+    /// Handles MCCP2 (`Compress2`) on its own.
+    ///
+    /// When enabled, a `WILL COMPRESS2` from the remote host is answered with `DO COMPRESS2`, and
+    /// zlib decompression begins right after the `IAC SB COMPRESS2 IAC SE` which follows, including
+    /// for any compressed bytes received in the same read. The negotiation and subnegotiation are
+    /// still returned as events. It is disabled by default.
+    #[cfg(feature = "zcstream")]
+    pub fn set_auto_mccp(&mut self, enabled: bool) {
+        self.auto_mccp = enabled;
+    }
+
     #[cfg(feature = "zcstream")]
     pub fn end_zlib(&mut self) {
         self.stream.end_zlib();
     }
 
+    /// Whether zlib decompression (MCCP2) is currently enabled.
+    #[cfg(feature = "zcstream")]
+    pub fn is_zlib_active(&self) -> bool {
+        self.stream.is_zlib_active()
+    }
+
     /// Open a telnet connection to a remote host using a generic stream.
     ///
     /// Communication will be made with the host using `stream`. `buf_size` is the size of the underlying
@@ -176,18 +658,121 @@ impl Telnet {
     /// Use this version of the constructor if you want to provide your own stream, for example if you want
     /// to mock out the remote host for testing purposes, or want to wrap the data with TLS encryption.
     pub fn from_stream(stream: Box<TStream>, buf_size: usize) -> Telnet {
+        Telnet::with_event_queue(stream, buf_size, TelnetEventQueue::new())
+    }
+
+    /// Open a telnet connection using a generic stream, preallocating room for `event_capacity`
+    /// queued events.
+    ///
+    /// This is the same as [`Telnet::from_stream`], except that the event queue will not need to
+    /// grow until more than `event_capacity` events are waiting to be read.
+    pub fn from_stream_with_capacity(
+        stream: Box<TStream>,
+        buf_size: usize,
+        event_capacity: usize,
+    ) -> Telnet {
+        Telnet::with_event_queue(
+            stream,
+            buf_size,
+            TelnetEventQueue::with_capacity(event_capacity),
+        )
+    }
+
+    /// Open a telnet connection using a generic stream, buffering up to `write_buf_size` bytes of
+    /// writes.
+    ///
+    /// Small writes, such as a sequence of negotiations, are collected and passed on to `stream`
+    /// in a single write, which saves system calls. The buffer is written out when it is full,
+    /// when [`Telnet::flush`] is called, and before waiting for data from the remote host.
+    pub fn from_stream_buffered(
+        stream: Box<TStream>,
+        buf_size: usize,
+        write_buf_size: usize,
+    ) -> Telnet {
+        let stream = bufstream::BufferedStream::new(stream, write_buf_size);
+        Telnet::from_stream(Box::new(stream), buf_size)
+    }
+
+    fn with_event_queue(
+        stream: Box<TStream>,
+        buf_size: usize,
+        event_queue: TelnetEventQueue,
+    ) -> Telnet {
         let actual_size = if buf_size == 0 { 1 } else { buf_size };
 
         Telnet {
             stream,
-            event_queue: TelnetEventQueue::new(),
+            event_queue,
+            options: OptionStates::new(),
+            negotiation_log: NegotiationLog::new(),
+            window_size: None,
+            subnegotiation_policy: subneg::SubnegotiationPolicy::default(),
+            deferred_subnegotiations: Vec::new(),
+            input_encoding: Box::new(encoding::Utf8),
+            input_pending: Vec::new(),
+            output_encoding: Box::new(encoding::Utf8),
             buffer: vec![0; actual_size].into_boxed_slice(),
             buffered_size: 0,
-            process_buffer: vec![0; actual_size].into_boxed_slice(),
-            process_buffered_size: 0,
+            state: ProcessState::NormalData,
+            sb_buffer: Vec::new(),
+            max_subnegotiation_len: None,
+            subnegotiation_limits: BTreeMap::new(),
+            raw_commands: false,
+            unknown_iac_handler: None,
+            command_params: 0,
+            raw_frames: false,
+            decode_terminal_type: false,
+            strict_subnegotiation: false,
+            strict_negotiation: false,
+            lenient_se_after: None,
+            passthrough: false,
+            auto_detect: false,
+            auto_detect_seen: 0,
+            negotiation_transparent: false,
+            auto_respond: None,
+            errors_as_io_error: false,
+            #[cfg(feature = "zcstream")]
+            auto_mccp: false,
+            allow_unsolicited_subnegotiation: true,
+            utf8_reassembly: false,
+            utf8_pending: Vec::new(),
+            bare_cr_policy: BareCrPolicy::Passthrough,
+            cr_pending: false,
+            max_write_chunk: None,
+            corked: false,
+            cork_buffer: None,
+            borrowed_data: None,
+            outbound: Vec::new(),
+            coalesce: None,
+            coalesced: Vec::new(),
+            coalesce_deadline: None,
+            idle_timeout: None,
+            default_read_timeout: None,
+            stream_nonblocking: None,
+            stream_read_timeout: None,
+            last_data: Instant::now(),
+            last_activity: Instant::now(),
         }
     }
 
+    /// The raw file descriptor of the underlying socket, or `None` if the stream has none.
+    ///
+    /// This allows registering the connection in an external event loop (such as `mio` or
+    /// `epoll`), and calling [`Telnet::read_nonblocking`] whenever it is readable.
+    #[cfg(unix)]
+    pub fn as_raw_fd(&self) -> Option<std::os::unix::io::RawFd> {
+        self.stream.as_raw_fd()
+    }
+
+    /// The raw socket of the underlying socket, or `None` if the stream has none.
+    ///
+    /// This allows registering the connection in an external event loop, and calling
+    /// [`Telnet::read_nonblocking`] whenever it is readable.
+    #[cfg(windows)]
+    pub fn as_raw_socket(&self) -> Option<std::os::windows::io::RawSocket> {
+        self.stream.as_raw_socket()
+    }
+
     /// Reads an [`Event`].
     ///
     /// If there was not any queued [`Event`], it would read a chunk of data into its buffer,
@@ -206,29 +791,19 @@ impl Telnet {
     /// # Errors
     /// - Read stream fails
     /// - Set stream settings fails
+    /// - [`ErrorKind::UnexpectedEof`] if the remote host closed the connection
     pub fn read(&mut self) -> io::Result<Event> {
-        while self.event_queue.is_empty() {
-            // Set stream settings
-            self.stream.set_nonblocking(false)?;
-            self.stream.set_read_timeout(None)?;
-
-            // Read bytes to the buffer
-            self.buffered_size = self.stream.read(&mut self.buffer)?;
-
-            self.process();
+        match self.fill_queue()? {
+            Some(event) => Ok(event),
+            None => self.take_queued_event(),
         }
-
-        // Return an event
-        Ok(self
-            .event_queue
-            .take_event()
-            .unwrap_or(Event::Error(InternalQueueErr)))
     }
 
     /// Reads an [`Event`], but the waiting time cannot exceed a given [`Duration`].
     ///
     /// This method is similar to [`Telnet::read`], but with a time limitation. If the given time was
-    /// reached, it would return [`Event::TimedOut`].
+    /// reached, it would return [`Event::TimedOut`]. It never returns [`Event::TimedOut`] before the
+    /// given time has passed, even if the stream gives up waiting early.
     ///
     /// # Examples
     /// ```rust,should_panic
@@ -243,29 +818,12 @@ impl Telnet {
     /// # Errors
     /// - Set stream settings fails
     /// - Read stream fails
+    /// - [`ErrorKind::UnexpectedEof`] if the remote host closed the connection
     pub fn read_timeout(&mut self, timeout: Duration) -> io::Result<Event> {
-        if self.event_queue.is_empty() {
-            // Set stream settings
-            self.stream.set_nonblocking(false)?;
-            self.stream.set_read_timeout(Some(timeout))?;
-
-            // Read bytes to the buffer
-            match self.stream.read(&mut self.buffer) {
-                Ok(size) => self.buffered_size = size,
-                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
-                    return Ok(Event::TimedOut)
-                }
-                Err(e) => return Err(e),
-            }
-
-            self.process();
+        match self.fill_queue_timeout(timeout)? {
+            Some(event) => Ok(event),
+            None => self.take_queued_event(),
         }
-
-        // Return an event
-        Ok(self
-            .event_queue
-            .take_event()
-            .unwrap_or(Event::Error(InternalQueueErr)))
     }
 
     /// Reads an [`Event`]. Returns immediately if there was no queued event and nothing to read.
@@ -285,374 +843,4824 @@ impl Telnet {
     /// # Errors
     /// - Set stream settings fails
     /// - Read stream fails
+    /// - [`ErrorKind::UnexpectedEof`] if the remote host closed the connection
     pub fn read_nonblocking(&mut self) -> io::Result<Event> {
         if self.event_queue.is_empty() {
             // Set stream settings
-            self.stream.set_nonblocking(true)?;
-            self.stream.set_read_timeout(None)?;
+            self.send_backlog()?;
+            self.stream.flush()?;
+            self.set_stream_nonblocking(true)?;
+            self.set_stream_read_timeout(None)?;
 
-            // Read bytes to the buffer
-            match self.stream.read(&mut self.buffer) {
-                Ok(size) => self.buffered_size = size,
-                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(Event::NoData),
-                Err(e) => return Err(e),
-            }
+            // Read bytes to the buffer, until a complete event arrives
+            while self.event_queue.is_empty() {
+                match self.stream.read(&mut self.buffer) {
+                    Ok(size) => self.buffered_size = size,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        self.release_cr();
+                        if self.event_queue.is_empty() {
+                            return Ok(Event::NoData);
+                        }
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                }
 
-            self.process();
+                self.process_or_flush()?;
+                self.flush_coalesced();
+            }
         }
 
         // Return an event
-        Ok(self
-            .event_queue
-            .take_event()
-            .unwrap_or(Event::Error(InternalQueueErr)))
+        self.take_queued_event()
     }
 
-    /// Writes a given data block to the remote host. It will double any IAC byte.
+    /// Reads once from the stream, and passes every event of the read to `f`, in order.
+    ///
+    /// This is the push counterpart of [`Telnet::read`], for callers which only pass the data on,
+    /// such as a pipe to a file. The data is passed as [`EventRef::Data`], borrowed from the read
+    /// buffer instead of being copied into a new [`Event::Data`], so a large stream is read
+    /// without an allocation per read. The slice is only valid during the call to `f`, and must be
+    /// copied to be kept. The data is still copied when UTF-8 reassembly, a bare CR policy, data
+    /// coalescing or transparent negotiation needs it. The events queued by earlier reads are
+    /// passed first. Errors are passed as [`Event::Error`].
     ///
     /// # Examples
     /// ```rust,should_panic
-    /// use telnet::Telnet;
+    /// use std::io::Write;
+    /// use telnet::{EventRef, Telnet};
     ///
     /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
     ///         .expect("Couldn't connect to the server...");
-    /// let buffer: [u8; 4] = [83, 76, 77, 84];
-    /// connection.write(&buffer).expect("Write Error");
+    /// let mut out = std::io::stdout();
+    /// connection
+    ///     .read_with(|event| {
+    ///         if let EventRef::Data(data) = event {
+    ///             out.write_all(data).expect("Write Error");
+    ///         }
+    ///     })
+    ///     .expect("Read Error");
     /// ```
     ///
     /// # Errors
-    /// - Write to stream fails
-    pub fn write(&mut self, data: &[u8]) -> io::Result<usize> {
-        let mut write_size = 0;
+    /// - Read stream fails, including when the read timeout set by [`Telnet::set_read_timeout`]
+    ///   expires
+    /// - [`ErrorKind::UnexpectedEof`] if the remote host closed the connection
+    pub fn read_with<F>(&mut self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(EventRef<'_>),
+    {
+        // Events of earlier reads come first
+        while let Some(event) = self.event_queue.take_event() {
+            pass_event(&mut f, event);
+        }
+
+        self.send_backlog()?;
+        self.stream.flush()?;
+        self.set_stream_nonblocking(false)?;
+        self.set_stream_read_timeout(self.default_read_timeout)?;
+        self.buffered_size = self.stream.read(&mut self.buffer)?;
+
+        let mut ranges = std::mem::take(&mut self.borrowed_data).unwrap_or_default();
+        ranges.clear();
+        self.borrowed_data = Some(ranges);
+        let result = self.process_or_flush();
+        let ranges = self.borrowed_data.take().unwrap_or_default();
 
-        let mut start = 0;
-        for i in 0..data.len() {
-            if data[i] == BYTE_IAC {
-                self.stream.write_all(&data[start..=i])?;
-                self.stream.write_all(&[BYTE_IAC])?;
-                write_size += i + 1 - start;
-                start = i + 1;
+        let mut passed = 0;
+        for (queued_before, range) in &ranges {
+            while passed < *queued_before {
+                if let Some(event) = self.event_queue.take_event() {
+                    pass_event(&mut f, event);
+                }
+                passed += 1;
             }
+            f(EventRef::Data(&self.buffer[range.clone()]));
         }
-
-        if start < data.len() {
-            self.stream.write_all(&data[start..data.len()])?;
-            write_size += data.len() - start;
+        while let Some(event) = self.event_queue.take_event() {
+            pass_event(&mut f, event);
         }
-
-        Ok(write_size)
+        // Kept for its capacity
+        self.borrowed_data = Some(ranges);
+        result
     }
 
-    /// Negotiates a telnet option with the remote host.
+    /// Reads as [`Telnet::read`] does, but appends every queued event to `out`, in order, rather
+    /// than returning only the first.
+    ///
+    /// Returns the number of events appended. This is an alternative to calling [`Telnet::read`]
+    /// once per event: the caller can clear `out` and reuse it for the next read, so that its
+    /// allocation is kept. If events were queued by earlier reads, they are appended without
+    /// reading. As with [`Telnet::read`], [`Event::Idle`] or [`Event::TimedOut`] is appended alone
+    /// when the wait ends without any event, and with [`Telnet::set_errors_as_io_error`], an
+    /// [`Event::Error`] is returned as the error, after appending the events before it.
     ///
     /// # Examples
     /// ```rust,should_panic
-    /// use telnet::{Telnet, Action, TelnetOption};
+    /// use telnet::Telnet;
     ///
     /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
     ///         .expect("Couldn't connect to the server...");
-    /// connection.negotiate(&Action::Will, TelnetOption::Echo);
+    /// let mut events = Vec::new();
+    /// loop {
+    ///     events.clear();
+    ///     connection.read_into(&mut events).expect("Read Error");
+    ///     for event in &events {
+    ///         println!("{:?}", event);
+    ///     }
+    /// }
     /// ```
     ///
     /// # Errors
-    /// - [`TelnetError::NegotiationErr`] if negotiation fails
-    pub fn negotiate(&mut self, action: &Action, opt: TelnetOption) -> Result<(), TelnetError> {
-        let buf = &[BYTE_IAC, action.as_byte(), opt.as_byte()];
-        self.stream.write_all(buf).or(Err(NegotiationErr))?;
-        Ok(())
-    }
+    /// - Read stream fails
+    /// - Set stream settings fails
+    /// - [`ErrorKind::UnexpectedEof`] if the remote host closed the connection
+    pub fn read_into(&mut self, out: &mut Vec<Event>) -> io::Result<usize> {
+        if let Some(event) = self.fill_queue()? {
+            out.push(event);
+            return Ok(1);
+        }
 
-    /// Send data for sub-negotiation with the remote host.
+        let start = out.len();
+        while !self.event_queue.is_empty() {
+            out.push(self.take_queued_event()?);
+        }
+        Ok(out.len() - start)
+    }
+
+    /// Returns an iterator over the events read before `deadline`.
+    ///
+    /// Events are yielded as they arrive, with [`Telnet::read_timeout`] bounded by the time left
+    /// until the deadline. Once the deadline passes, [`Event::TimedOut`] is yielded as the last item.
+    /// The iteration also ends after the first error.
     ///
     /// # Examples
     /// ```rust,should_panic
-    /// use telnet::{Telnet, Action, TelnetOption};
+    /// use std::time::{Duration, Instant};
+    /// use telnet::Telnet;
     ///
     /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
     ///         .expect("Couldn't connect to the server...");
-    /// connection.negotiate(&Action::Do, TelnetOption::TTYPE);
-    /// let data: [u8; 1] = [1];
-    /// connection.subnegotiate(TelnetOption::TTYPE, &data);
+    /// let deadline = Instant::now() + Duration::from_secs(3);
+    /// for event in connection.events_until(deadline) {
+    ///     println!("{:?}", event.expect("Read Error"));
+    /// }
     /// ```
+    pub fn events_until(
+        &mut self,
+        deadline: Instant,
+    ) -> impl Iterator<Item = io::Result<Event>> + '_ {
+        iter::EventsUntil::new(self, deadline)
+    }
+
+    /// Returns a [`BufRead`](std::io::BufRead) over the data received, for line-oriented reading.
     ///
-    /// # Errors
-    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
-    #[allow(clippy::shadow_unrelated)]
-    pub fn subnegotiate(&mut self, opt: TelnetOption, data: &[u8]) -> Result<(), TelnetError> {
-        let buf = &[BYTE_IAC, BYTE_SB, opt.as_byte()];
-        self.stream
-            .write_all(buf)
-            .or(Err(SubnegotiationErr(SubnegotiationType::Start)))?;
+    /// The reader returns the payload of [`Event::Data`] only, so `read_line` and `lines` work on
+    /// the text as sent by the remote host. The other events are side-channeled: they are set
+    /// aside while reading, and can be taken with [`DataReader::take_event`]. Whatever is left
+    /// when the reader is dropped is queued again, to be returned by the next reads. The end of the
+    /// data is reached when the remote host closes the connection.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use std::io::BufRead;
+    /// use telnet::Telnet;
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// let mut reader = connection.data_reader();
+    /// let mut line = String::new();
+    /// reader.read_line(&mut line).expect("Read Error");
+    /// while let Some(event) = reader.take_event() {
+    ///     println!("{:?}", event);
+    /// }
+    /// ```
+    pub fn data_reader(&mut self) -> DataReader<'_> {
+        DataReader::new(self)
+    }
 
-        self.stream
-            .write_all(data)
-            .or(Err(SubnegotiationErr(SubnegotiationType::Data)))?;
+    /// Reads for `timeout` and returns all the data received, for callers which only want the text.
+    ///
+    /// Every request of the remote host to enable an option is refused, unless it was already
+    /// answered, for example by [`Telnet::set_window_size`]. The other events are dropped. Reading
+    /// stops early if the remote host closes the connection.
+    ///
+    /// # Errors
+    /// - Read stream fails
+    /// - Write to stream fails when refusing an option
+    /// - [`ErrorKind::UnexpectedEof`] if the remote host closed the connection before sending any data
+    pub fn read_text(&mut self, timeout: Duration) -> io::Result<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        let mut text = Vec::new();
 
-        let buf = &[BYTE_IAC, BYTE_SE];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
 
-        self.stream
-            .write_all(buf)
-            .or(Err(SubnegotiationErr(SubnegotiationType::End)))?;
+            match self.read_timeout(remaining) {
+                Ok(Event::Data(data)) => text.extend_from_slice(&data),
+                Ok(Event::Negotiation(action, opt)) => {
+                    if self.options.is_awaiting_reply(action, opt) {
+                        if let Some(reply) = action.refuse() {
+                            self.negotiate(&reply, opt)?;
+                        }
+                    }
+                }
+                Ok(Event::TimedOut) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && !text.is_empty() => break,
+                Err(e) => return Err(e),
+            }
+        }
 
-        Ok(())
+        Ok(text)
     }
 
-    #[allow(clippy::too_many_lines)]
-    fn process(&mut self) {
-        let mut current = 0;
-        let mut state = ProcessState::NormalData;
-        let mut data_start = 0;
-
-        while current < self.buffered_size {
-            // Gather a byte
-            let byte = self.buffer[current];
-
-            match state {
-                ProcessState::NormalData => {
-                    if byte == BYTE_IAC {
-                        // The following bytes will be commands
-                        // Update the state
-                        state = ProcessState::IAC;
-
-                        // Send the data before this byte
-                        if current > data_start {
-                            let data_end = current;
-                            let data = self.copy_buffered_data(data_start, data_end);
-                            self.event_queue.push_event(Event::Data(data));
+    /// Reads events for `timeout`, writing the payload of every [`Event::Data`] to `w`.
+    ///
+    /// This is a shortcut for dumping a session to a file or a terminal. The other events are not
+    /// lost: they are queued again, in order, to be returned by the next reads. Piping stops early
+    /// if the remote host closes the connection. Returns the number of bytes written to `w`.
+    ///
+    /// # Errors
+    /// - Read stream fails
+    /// - Write to `w` fails
+    pub fn pipe_data_to<W: Write>(&mut self, w: &mut W, timeout: Duration) -> io::Result<usize> {
+        let mut written = 0;
+        let mut kept = Vec::new();
 
-                            // Update the state
-                            data_start = current;
-                        }
-                    } else if current == self.buffered_size - 1 {
-                        // If it reaches the end of the buffer
-                        let data_end = self.buffered_size;
-                        let data = self.copy_buffered_data(data_start, data_end);
-                        self.event_queue.push_event(Event::Data(data));
+        let mut result = Ok(());
+        for event in self.events_until(Instant::now() + timeout) {
+            match event {
+                Ok(Event::Data(data)) => {
+                    if let Err(e) = w.write_all(&data) {
+                        result = Err(e);
+                        break;
                     }
+                    written += data.len();
                 }
+                Ok(Event::TimedOut) => {}
+                Ok(event) => kept.push(event),
+                // Left for the next read to report
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {}
+                Err(e) => result = Err(e),
+            }
+        }
 
-                // Telnet Commands
-                ProcessState::IAC => {
-                    match byte {
-                        // Negotiation Commands
-                        BYTE_WILL => state = ProcessState::Will,
-                        BYTE_WONT => state = ProcessState::Wont,
-                        BYTE_DO => state = ProcessState::Do,
-                        BYTE_DONT => state = ProcessState::Dont,
-                        // Subnegotiation
-                        BYTE_SB => state = ProcessState::SB,
-                        // Escaping
-                        // TODO: Write a test case for this
-                        BYTE_IAC => {
-                            // Copy the data to the process buffer
-                            self.append_data_to_proc_buffer(data_start, current - 1);
+        self.requeue_front(kept);
+        result.map(|()| written)
+    }
 
-                            // Add escaped IAC
-                            self.process_buffer[self.process_buffered_size] = BYTE_IAC;
-                            self.process_buffered_size += 1;
+    /// Reads events until exactly `n` bytes of data are received, and returns them.
+    ///
+    /// This suits binary protocols layered over telnet, for example after TRANSMIT-BINARY is
+    /// agreed to. Data received beyond `n` bytes, and the other events, are queued again, in order,
+    /// to be returned by the next reads.
+    ///
+    /// # Errors
+    /// - Read stream fails
+    /// - [`ErrorKind::TimedOut`] if fewer than `n` bytes arrive within `timeout`
+    /// - [`ErrorKind::UnexpectedEof`] if the remote host closes the connection before `n` bytes
+    ///   arrive
+    ///
+    /// The data received is not lost on error, and is returned by the next reads.
+    pub fn read_exact_data(&mut self, n: usize, timeout: Duration) -> io::Result<Vec<u8>> {
+        let mut events = Vec::new();
+        let mut received = 0;
 
-                            // Update the state
-                            state = ProcessState::NormalData;
-                            data_start = current + 1;
+        let mut result = Ok(());
+        if n > 0 {
+            result = Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "not enough data received",
+            ));
+            for event in self.events_until(Instant::now() + timeout) {
+                match event {
+                    Ok(Event::TimedOut) => {}
+                    Ok(event) => {
+                        if let Event::Data(ref data) = event {
+                            received += data.len();
                         }
-                        // Unknown IAC commands
-                        _ => {
-                            state = ProcessState::NormalData;
-                            data_start = current + 1;
-                            self.event_queue.push_event(Event::UnknownIAC(byte));
+                        events.push(event);
+                        if received >= n {
+                            result = Ok(());
+                            break;
                         }
                     }
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
                 }
+            }
+        }
 
-                // Negotiation
-                ProcessState::Will | ProcessState::Wont | ProcessState::Do | ProcessState::Dont => {
-                    let opt = TelnetOption::parse(byte);
+        if let Err(e) = result {
+            self.requeue_front(events);
+            return Err(e);
+        }
 
-                    match state {
-                        ProcessState::Will => {
-                            self.event_queue
-                                .push_event(Event::Negotiation(Action::Will, opt));
-                        }
-                        ProcessState::Wont => {
-                            self.event_queue
-                                .push_event(Event::Negotiation(Action::Wont, opt));
-                        }
-                        ProcessState::Do => {
-                            self.event_queue
-                                .push_event(Event::Negotiation(Action::Do, opt));
-                        }
-                        ProcessState::Dont => {
-                            self.event_queue
-                                .push_event(Event::Negotiation(Action::Dont, opt));
-                        }
-                        _ => {} // Do nothing
+        // Take the first `n` bytes, keeping the rest of the data and the other events
+        let mut data = Vec::with_capacity(n);
+        let mut kept = Vec::new();
+        for event in events {
+            match event {
+                Event::Data(chunk) if data.len() < n => {
+                    let take = (n - data.len()).min(chunk.len());
+                    data.extend_from_slice(&chunk[..take]);
+                    if take < chunk.len() {
+                        kept.push(Event::Data(Box::from(&chunk[take..])));
                     }
-
-                    state = ProcessState::NormalData;
-                    data_start = current + 1;
                 }
+                event => kept.push(event),
+            }
+        }
+        self.requeue_front(kept);
 
-                // Start subnegotiation
-                ProcessState::SB => {
-                    let opt = TelnetOption::parse(byte);
-                    state = ProcessState::SBData(opt, current + 1);
-                }
+        Ok(data)
+    }
 
-                // Subnegotiation's data
-                ProcessState::SBData(opt, data_start) => {
-                    if byte == BYTE_IAC {
-                        state = ProcessState::SBDataIAC(opt, data_start);
-                    }
+    /// Returns a guard whose reads and writes all stop at `deadline`.
+    ///
+    /// This bounds a whole exchange, such as sending a command and reading up to the next prompt,
+    /// with a single time limit instead of one per operation.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use std::time::{Duration, Instant};
+    /// use telnet::{Event, Telnet};
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// let mut exchange = connection.with_deadline(Instant::now() + Duration::from_secs(5));
+    /// exchange.write(b"who\r\n").expect("Write Error");
+    /// loop {
+    ///     match exchange.read().expect("Read Error") {
+    ///         Event::Data(data) if data.ends_with(b"> ") => break,
+    ///         Event::TimedOut => panic!("No prompt in time"),
+    ///         _ => {}
+    ///     }
+    /// }
+    /// ```
+    pub fn with_deadline(&mut self, deadline: Instant) -> Deadline<'_> {
+        Deadline::new(self, deadline)
+    }
 
-                    // XXX: We may need to consider the case that a SB Data
-                    // sequence may exceed this buffer
-                }
+    /// When data was last received from or written to the remote host, or when the connection
+    /// was opened if nothing was exchanged yet.
+    ///
+    /// This can be used by a connection pool to close the connections idle for too long.
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity
+    }
 
-                // IAC inside Subnegotiation's data
-                ProcessState::SBDataIAC(opt, sb_data_start) => {
-                    match byte {
-                        // The end of subnegotiation
-                        BYTE_SE => {
-                            // Update state
-                            state = ProcessState::NormalData;
-                            data_start = current + 1;
+    /// The number of bytes which can be read from the stream without blocking.
+    ///
+    /// This lets an event loop check for incoming data before calling [`Telnet::read`]. The
+    /// bytes are counted as received, before any telnet command is parsed or compressed data is
+    /// inflated, and up to 16 KiB on a [`TcpStream`]. Events already queued are not counted, see
+    /// [`Telnet::queued_event_count`].
+    ///
+    /// # Errors
+    /// - [`ErrorKind::Unsupported`] if the stream cannot tell, which is the case of a
+    ///   [`TcpStream`] on platforms other than unix
+    pub fn available(&self) -> io::Result<usize> {
+        self.stream.available()
+    }
 
-                            // Return the option
-                            let sb_data_end = current - 1;
-                            let data = self.copy_buffered_data(sb_data_start, sb_data_end);
-                            self.event_queue
-                                .push_event(Event::Subnegotiation(opt, data));
-                        }
-                        // Escaping
-                        // TODO: Write a test case for this
-                        BYTE_IAC => {
-                            // Copy the data to the process buffer
-                            self.append_data_to_proc_buffer(sb_data_start, current - 1);
+    /// Takes the pending error of the underlying socket, if any.
+    ///
+    /// With a nonblocking socket, an error can happen asynchronously (`SO_ERROR`) without being
+    /// reported by the next read. This lets an event loop check for it. Streams which are not
+    /// sockets return `Ok(None)`.
+    ///
+    /// # Errors
+    /// - Any error of the stream while retrieving the pending error
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.stream.take_error()
+    }
 
-                            // Add escaped IAC
-                            self.process_buffer[self.process_buffered_size] = BYTE_IAC;
-                            self.process_buffered_size += 1;
+    /// Sets how long closing the connection waits for unsent data to be delivered (`SO_LINGER`).
+    ///
+    /// With `Some(duration)`, dropping the `Telnet` blocks until the data written last, such as a
+    /// reply to a logout, is sent, for at most `duration`, rounded up to whole seconds. With
+    /// `Some(Duration::ZERO)`, unsent data is discarded and the connection is reset. With `None`,
+    /// the default, closing returns at once and the system sends the data in the background.
+    ///
+    /// # Errors
+    /// - [`ErrorKind::Unsupported`] if the stream is not a socket which supports it
+    /// - Setting the socket option fails
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        self.stream.set_linger(linger)
+    }
 
-                            // Update the state
-                            state = ProcessState::SBData(opt, current + 1);
-                        }
-                        // TODO: Write a test case for this
-                        b => {
-                            self.event_queue.push_event(Event::Error(UnexpectedByte(b)));
+    /// The size of the buffer used for reading from the stream.
+    pub fn buffer_capacity(&self) -> usize {
+        self.buffer.len()
+    }
 
-                            // Copy the data to the process buffer
-                            self.append_data_to_proc_buffer(sb_data_start, current - 1);
-                            // Update the state
-                            state = ProcessState::SBData(opt, current + 1);
-                        }
-                    }
-                }
-            }
+    /// The number of events read from the stream and waiting to be returned.
+    pub fn queued_event_count(&self) -> usize {
+        self.event_queue.len()
+    }
 
-            // Move to the next byte
-            current += 1;
-        }
+    /// Sets how long [`Telnet::read`] waits for data before returning [`Event::Idle`].
+    ///
+    /// The idle time is counted from the last time data was received, or from the last
+    /// [`Event::Idle`]. This can be used to detect a hung connection. With `None`, the default,
+    /// [`Telnet::read`] blocks until data arrives.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+        self.last_data = Instant::now();
     }
 
-    // Copy the data to the process buffer
-    fn append_data_to_proc_buffer(&mut self, data_start: usize, data_end: usize) {
-        let data_length = data_end - data_start;
-        let dst_start = self.process_buffered_size;
-        let dst_end = self.process_buffered_size + data_length;
-        let dst = &mut self.process_buffer[dst_start..dst_end];
-        dst.copy_from_slice(&self.buffer[data_start..data_end]);
-        self.process_buffered_size += data_length;
+    /// Limits how many events can be waiting in the queue at once.
+    ///
+    /// This bounds the memory used when the remote host sends events faster than they are read,
+    /// for example by flooding negotiations. Once the limit is reached, events are dropped
+    /// according to the policy set by [`Telnet::set_queue_overflow_policy`], and an
+    /// [`Event::Error`] with [`TelnetError::QueueOverflow`] is queued in their place. The limit
+    /// cannot be lower than 2. There is no limit by default.
+    pub fn set_max_queued_events(&mut self, max: usize) {
+        self.event_queue.set_max_len(max);
     }
 
-    fn copy_buffered_data(&mut self, data_start: usize, data_end: usize) -> Box<[u8]> {
-        let data = if self.process_buffered_size > 0 {
-            // Copy the data to the process buffer
-            self.append_data_to_proc_buffer(data_start, data_end);
+    /// Sets which events are dropped when the limit set by [`Telnet::set_max_queued_events`] is
+    /// reached. Defaults to [`QueueOverflowPolicy::DropNewest`].
+    pub fn set_queue_overflow_policy(&mut self, policy: QueueOverflowPolicy) {
+        self.event_queue.set_overflow_policy(policy);
+    }
 
-            let pbe = self.process_buffered_size;
-            self.process_buffered_size = 0;
+    /// Calls `handler` with every unknown command received after IAC, instead of reporting it as
+    /// [`Event::UnknownIAC`].
+    ///
+    /// This allows handling experimental or vendor-specific commands. The handler may write to the
+    /// connection, for example to answer the command, and may call
+    /// [`Telnet::consume_command_params`] for commands followed by parameters. It must not read
+    /// from the connection, as it runs while the received data is being parsed. It takes
+    /// precedence over [`Telnet::set_raw_commands`].
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::Telnet;
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// connection.set_unknown_iac_handler(|command, telnet| {
+    ///     // A vendor command followed by 2 parameter bytes
+    ///     if command == 0x10 {
+    ///         telnet.consume_command_params(2);
+    ///     }
+    /// });
+    /// ```
+    pub fn set_unknown_iac_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(u8, &mut Telnet) + 'static,
+    {
+        self.unknown_iac_handler = Some(Box::new(handler));
+    }
 
-            &self.process_buffer[0..pbe]
-        } else {
-            &self.buffer[data_start..data_end]
-        };
+    /// Collects the next `count` bytes received as the parameters of the unknown command being
+    /// handled, instead of data.
+    ///
+    /// This is meant to be called from the handler set with [`Telnet::set_unknown_iac_handler`].
+    /// The command and its parameters are then reported together as an [`Event::RawCommand`],
+    /// even when they are split across reads.
+    pub fn consume_command_params(&mut self, count: usize) {
+        self.command_params = count;
+    }
 
-        Box::from(data)
+    /// Reports unknown commands together with the bytes following them.
+    ///
+    /// By default, an unknown command after IAC is reported as [`Event::UnknownIAC`] and the
+    /// following bytes are treated as data. When enabled, it is reported as [`Event::RawCommand`]
+    /// instead, holding the command byte and every byte after it up to the next IAC or the end of
+    /// the current read, whichever comes first. Those bytes are not reported as data. This is only a
+    /// heuristic, as telnet gives no way to know the length of a command it does not define.
+    pub fn set_raw_commands(&mut self, enabled: bool) {
+        self.raw_commands = enabled;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Error;
+    /// Reports the raw bytes of every negotiation and subnegotiation.
+    ///
+    /// When enabled, each [`Event::Negotiation`] and each subnegotiation event is preceded by an
+    /// [`Event::RawFrame`] holding its bytes, from `IAC` to the option byte or to `IAC SE`. This is
+    /// meant for protocol analyzers. It is disabled by default.
+    ///
+    /// The frame is encoded again from what was parsed, so it is not always the bytes received: a
+    /// byte skipped after `IAC` inside a subnegotiation is left out, and a subnegotiation ended by a
+    /// bare `SE` ends with `IAC SE`.
+    pub fn set_raw_frames(&mut self, enabled: bool) {
+        self.raw_frames = enabled;
+    }
 
-    struct MockStream {
-        test_data: Vec<u8>,
+    /// Delivers TTYPE subnegotiations as [`Event::TerminalType`] and
+    /// [`Event::TerminalTypeRequested`].
+    ///
+    /// It is disabled by default, and TTYPE subnegotiations are delivered as
+    /// [`Event::Subnegotiation`] like those of the other options without a decoder. Malformed ones
+    /// are always delivered that way.
+    pub fn set_decode_terminal_type(&mut self, enabled: bool) {
+        self.decode_terminal_type = enabled;
     }
 
-    impl MockStream {
-        fn new(data: Vec<u8>) -> MockStream {
-            MockStream { test_data: data }
-        }
+    /// Sets whether a subnegotiation with an unexpected byte after IAC is dropped.
+    ///
+    /// Inside a subnegotiation, IAC may only be followed by SE or another IAC. By default, any other
+    /// byte is reported as [`TelnetError::UnexpectedByte`] and skipped, and the subnegotiation goes
+    /// on. In strict mode, the whole subnegotiation is dropped instead: a single
+    /// [`TelnetError::MalformedSubnegotiation`] is reported with the bytes received so far, and
+    /// everything up to the closing `IAC SE` is discarded.
+    pub fn set_strict_subnegotiation(&mut self, strict: bool) {
+        self.strict_subnegotiation = strict;
     }
 
-    impl stream::Stream for MockStream {
-        fn set_nonblocking(&self, _nonblocking: bool) -> Result<(), Error> {
-            Ok(())
-        }
+    /// Lets a bare SE byte end a subnegotiation, for servers which forget the IAC before it.
+    ///
+    /// By default, as RFC 854 requires, only `IAC SE` ends a subnegotiation, and an SE byte alone
+    /// is part of its data. A buggy server which sends SE alone then leaves the subnegotiation open,
+    /// swallowing everything that follows. With `Some(min_len)`, an SE received after at least
+    /// `min_len` bytes of data ends the subnegotiation too, after a
+    /// [`TelnetError::BareSubnegotiationEnd`] warning. Payloads shorter than `min_len` are still
+    /// read strictly, as a short binary payload may hold an SE byte on purpose.
+    pub fn set_lenient_subnegotiation_end(&mut self, min_len: Option<usize>) {
+        self.lenient_se_after = min_len;
+    }
 
-        fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<(), Error> {
-            Ok(())
-        }
+    /// Sets whether [`Telnet::negotiate`] rejects requests which make no sense.
+    ///
+    /// In strict mode, asking to enable an option which no RFC assigns, an
+    /// [`TelnetOption::UnknownOption`], fails with [`TelnetError::InvalidNegotiation`] and nothing
+    /// is sent. Disabling an option is always allowed, so unknown requests of the remote host can
+    /// still be refused. Experimental options can be negotiated with [`Telnet::negotiate_raw`],
+    /// which is never checked. It is disabled by default.
+    pub fn set_strict_negotiation(&mut self, strict: bool) {
+        self.strict_negotiation = strict;
     }
 
-    impl io::Read for MockStream {
-        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-            let mut offset = 0;
-            while offset < buf.len() && offset < self.test_data.len() {
-                buf[offset] = self.test_data[offset];
-                offset += 1;
-            }
-            Ok(offset)
-        }
+    /// Returns the errors found while processing the received data as `Err` from the read methods.
+    ///
+    /// By default, such errors are returned as [`Event::Error`], which a caller using `?` on the
+    /// result of a read does not notice. When enabled, they are returned as an [`io::Error`] of
+    /// kind [`ErrorKind::Other`] wrapping the [`TelnetError`] instead. The connection can still be
+    /// read from afterwards.
+    pub fn set_errors_as_io_error(&mut self, enabled: bool) {
+        self.errors_as_io_error = enabled;
     }
 
-    impl io::Write for MockStream {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            Ok(buf.len())
+    /// Merges the data on both sides of a negotiation into a single [`Event::Data`].
+    ///
+    /// By default, `data IAC WILL ECHO more` is returned as three events: the data before the
+    /// negotiation, the negotiation, and the data after it. When enabled, the negotiations of a
+    /// read are returned first, followed by all the data of the read up to the next event of
+    /// another kind, so a line is not split by a negotiation sent in its middle. The order of the
+    /// data itself, and of the events other than negotiations, is never changed. It is disabled by
+    /// default.
+    pub fn set_negotiation_transparent(&mut self, enabled: bool) {
+        self.negotiation_transparent = enabled;
+        if !enabled {
+            self.flush_coalesced();
         }
+    }
 
-        fn flush(&mut self) -> io::Result<()> {
-            Ok(())
-        }
+    /// Answers every request of the remote host to enable an option on its own.
+    ///
+    /// When set, a `WILL` or `DO` for one of the `accepted` options is accepted, and refused
+    /// otherwise, before the read returns. The [`Event::Negotiation`] is still returned, after the
+    /// reply went out, so the caller can log it or update a UI without having to answer it.
+    /// Requests already answered, for example by [`Telnet::set_window_size`], are left alone.
+    /// This is the recommended setup for new code. It is disabled by default, leaving every
+    /// request to the caller.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::{Telnet, TelnetOption};
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// connection.set_auto_respond(Some(&[TelnetOption::SuppressGoAhead]));
+    /// ```
+    pub fn set_auto_respond(&mut self, accepted: Option<&[TelnetOption]>) {
+        self.auto_respond = accepted.map(<[TelnetOption]>::to_vec);
     }
 
-    #[test]
-    fn escapes_double_iac_correctly() {
-        let stream = MockStream::new(vec![0x40, 0x5a, 0xff, 0xff, 0x31, 0x34]);
+    /// Stops parsing telnet commands, delivering every byte received from now on as data.
+    ///
+    /// Once SUPDUP (RFC 736) is agreed to, the connection carries the SUPDUP protocol instead of
+    /// telnet, and an IAC byte no longer starts a command. This is done automatically when SUPDUP
+    /// is agreed to with [`Telnet::negotiate`] and the read methods, and can be called directly
+    /// when the switch is made another way. There is no way back to telnet.
+    pub fn enter_supdup_mode(&mut self) {
+        self.passthrough = true;
+    }
 
-        #[cfg(feature = "zcstream")]
-        let stream = ZlibStream::from_stream(stream);
+    /// Sets how long [`Telnet::read`] waits for an event, making it the same as
+    /// [`Telnet::read_timeout`] with that timeout. It returns [`Event::TimedOut`] when no event
+    /// arrives in time. It waits without limit (`None`) by default.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.default_read_timeout = timeout;
+    }
 
-        let stream = Box::new(stream);
+    /// Enables or disables the detection of a remote host which does not speak telnet.
+    ///
+    /// Parsing a raw TCP service as telnet corrupts any 0xFF byte of its data. When enabled, if
+    /// the first 256 bytes received contain no IAC byte, the connection is taken for a raw one,
+    /// and every byte received from then on is delivered as data, as with
+    /// [`Telnet::enter_supdup_mode`]. An IAC byte among the first 256 bytes ends the detection,
+    /// the connection being telnet. This is a best-effort heuristic: a telnet server which sends a
+    /// long banner before negotiating anything is taken for a raw one. It is disabled by default.
+    pub fn set_auto_detect(&mut self, enabled: bool) {
+        self.auto_detect = enabled;
+    }
 
-        let mut telnet = Telnet::from_stream(stream, 6);
+    /// Whether telnet commands are no longer parsed, because SUPDUP was entered or a raw stream
+    /// was detected with [`Telnet::set_auto_detect`].
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough
+    }
 
-        let expected_bytes_1: [u8; 2] = [0x40, 0x5a];
-        let expected_bytes_2: [u8; 3] = [0xff, 0x31, 0x34];
+    /// Limits the length of the data of received subnegotiations, to protect against a remote
+    /// host sending endless ones.
+    ///
+    /// A subnegotiation whose data exceeds `max_len` bytes is dropped, and reported as an
+    /// [`Event::Error`] with [`TelnetError::SubnegotiationTooLong`]. The limit can be overridden
+    /// for single options with [`Telnet::set_subnegotiation_limit`]. There is no limit (`None`)
+    /// by default.
+    pub fn set_max_subnegotiation_len(&mut self, max_len: Option<usize>) {
+        self.max_subnegotiation_len = max_len;
+    }
 
-        let event_1 = telnet.read_nonblocking().unwrap();
-        if let Event::Data(buffer) = event_1 {
-            assert_eq!(buffer.as_ref(), &expected_bytes_1);
-        } else {
-            panic!();
+    /// Limits the length of the data of received subnegotiations for `opt`, overriding the limit
+    /// set by [`Telnet::set_max_subnegotiation_len`].
+    ///
+    /// This keeps options with small payloads, such as NAWS, tight, while leaving room for options
+    /// with large ones, such as GMCP.
+    pub fn set_subnegotiation_limit(&mut self, opt: TelnetOption, max_len: usize) {
+        self.subnegotiation_limits.insert(opt.as_byte(), max_len);
+    }
+
+    /// Sets whether subnegotiations are accepted for options which were not agreed to.
+    ///
+    /// Some remote hosts send a subnegotiation (such as `TTYPE SEND`) without negotiating the
+    /// option first. When disallowed, such a subnegotiation is reported as an [`Event::Error`] with
+    /// [`TelnetError::UnsolicitedSubnegotiation`] instead, which helps detecting misbehaving
+    /// hosts. They are allowed by default.
+    pub fn set_allow_unsolicited_subnegotiation(&mut self, allow: bool) {
+        self.allow_unsolicited_subnegotiation = allow;
+    }
+
+    /// Enables or disables UTF-8 reassembly of [`Event::Data`].
+    ///
+    /// When enabled, a multibyte UTF-8 character split across two reads is held back and
+    /// delivered whole with the next [`Event::Data`], so each data event can be decoded on its
+    /// own. It is disabled by default.
+    pub fn set_utf8_reassembly(&mut self, enabled: bool) {
+        self.utf8_reassembly = enabled;
+    }
+
+    /// Sets what to do with a bare CR, one not followed by LF or NUL, in [`Event::Data`].
+    ///
+    /// Unless the policy is [`BareCrPolicy::Passthrough`], the default, a CR ending a read is held
+    /// back until the next data tells whether it is bare. If a read times out or would block
+    /// first, the CR is treated as bare, so that a prompt ending with it is still delivered.
+    pub fn set_bare_cr_policy(&mut self, policy: BareCrPolicy) {
+        self.bare_cr_policy = policy;
+    }
+
+    /// Coalesces consecutive data from several reads into a single [`Event::Data`].
+    ///
+    /// With `Some((size, window))`, [`Telnet::read`] keeps reading while data arrives, emitting
+    /// the collected data once it reaches `size` bytes, once no more data arrives within `window`
+    /// of the first collected byte, or when any other event is received. This reduces the number of
+    /// events for servers sending data in many small pieces, at the cost of up to `window` of
+    /// latency. [`Telnet::read_timeout`] and [`Telnet::read_nonblocking`] do not wait for more
+    /// data and deliver whatever was collected at once. It is disabled (`None`) by default.
+    pub fn set_coalesce(&mut self, coalesce: Option<(usize, Duration)>) {
+        self.coalesce = coalesce;
+        if coalesce.is_none() {
+            self.flush_coalesced();
         }
+    }
 
-        let event_2 = telnet.read_nonblocking().unwrap();
-        if let Event::Data(buffer) = event_2 {
-            assert_eq!(buffer.as_ref(), &expected_bytes_2);
-        } else {
+    /// Parses `bytes` as if they were read from the stream, and queues the resulting events.
+    ///
+    /// Nothing is read from the stream. The events are then returned by the read methods, for
+    /// example [`Telnet::read_nonblocking`], before anything else is read. This is useful for
+    /// testing code built on `Telnet` and for replaying a captured session.
+    ///
+    /// Replies to the fed negotiations, such as those of [`Telnet::set_auto_respond`], are still
+    /// written to the stream. Fed bytes do not count as received: they do not restart the idle
+    /// timeout of [`Telnet::set_idle_timeout`], and [`Telnet::last_activity`] only moves if a reply
+    /// is written.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(self.buffer.len()) {
+            self.buffer[..chunk.len()].copy_from_slice(chunk);
+            self.buffered_size = chunk.len();
+            self.process();
+        }
+        self.flush_coalesced();
+    }
+
+    /// Queues any bytes held back by UTF-8 reassembly, the bare CR policy or data coalescing as an
+    /// [`Event::Data`].
+    ///
+    /// This is done automatically when the remote host closes the connection, so that an
+    /// incomplete character at the end of the stream is not lost. The bytes are delivered as-is,
+    /// and may not be valid UTF-8.
+    pub fn flush_pending(&mut self) {
+        if !self.utf8_pending.is_empty() {
+            let data = std::mem::take(&mut self.utf8_pending);
+            self.coalesced.extend_from_slice(&data);
+        }
+        if std::mem::take(&mut self.cr_pending) {
+            self.coalesced.extend(self.bare_cr_policy.bare_cr());
+        }
+        self.flush_coalesced();
+    }
+
+    /// Writes a given data block to the remote host. It will double any IAC byte.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::Telnet;
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// let buffer: [u8; 4] = [83, 76, 77, 84];
+    /// connection.write(&buffer).expect("Write Error");
+    /// ```
+    ///
+    /// If the stream would block, the bytes it did not take are kept as by [`Telnet::write_raw`],
+    /// and all of `data` counts as sent. If the stream fails after taking part of the data, the
+    /// number of bytes of `data` sent so far is returned instead of the error, as
+    /// [`Write::write`] does, so that the rest can be written again without sending anything
+    /// twice. An IAC byte sent without its escape counts as sent, and its escape is written first
+    /// by the next write. The error is returned when nothing was sent.
+    ///
+    /// # Errors
+    /// - Write to stream fails before any byte of `data` is sent
+    pub fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if let Some(max_chunk) = self.max_write_chunk {
+            return self.write_chunked(data, max_chunk);
+        }
+
+        // Fast path: most data, such as text, has no IAC byte to escape
+        if !data.contains(&BYTE_IAC) {
+            return match self.write_frame(data) {
+                Ok(()) => Ok(data.len()),
+                Err((accepted, e)) => partial_write(accepted, e),
+            };
+        }
+
+        let escaped = format::data(data);
+        match self.write_frame(&escaped) {
+            Ok(()) => Ok(data.len()),
+            Err((written, e)) => {
+                let sent = self.complete_escape(&escaped[..written]);
+                partial_write(sent, e)
+            }
+        }
+    }
+
+    /// Writes several data blocks to the remote host as one, doubling any IAC byte.
+    ///
+    /// This avoids joining the blocks beforehand, for example for a response assembled from
+    /// parts. They are escaped into a single buffer and written at once, or in chunks as set by
+    /// [`Telnet::set_max_write_chunk`]. Returns the total length of the blocks.
+    ///
+    /// A failure partway is reported as by [`Telnet::write`]: the number of bytes of the blocks
+    /// sent so far is returned, and the blocks after them are not written.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::Telnet;
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// let name = "world";
+    /// connection.write_chunks(["hello, ", name, "\r\n"]).expect("Write Error");
+    /// ```
+    ///
+    /// # Errors
+    /// - Write to stream fails before any byte of the blocks is sent
+    pub fn write_chunks<I>(&mut self, chunks: I) -> io::Result<usize>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut escaped = Vec::new();
+        let mut write_size = 0;
+        for chunk in chunks {
+            let chunk = chunk.as_ref();
+            if self.max_write_chunk.is_none() {
+                format::escape_to(chunk, &mut escaped);
+                write_size += chunk.len();
+                continue;
+            }
+            // A short write means the stream failed, so the next blocks are not written
+            match self.write(chunk) {
+                Ok(sent) if sent < chunk.len() => return Ok(write_size + sent),
+                Ok(sent) => write_size += sent,
+                Err(e) => return partial_write(write_size, e),
+            }
+        }
+
+        if let Err((written, e)) = self.write_frame(&escaped) {
+            let sent = self.complete_escape(&escaped[..written]);
+            return partial_write(sent, e);
+        }
+        Ok(write_size)
+    }
+
+    /// Limits the size of the writes of [`Telnet::write`] to the stream.
+    ///
+    /// With `Some(size)`, data is escaped and written in chunks of at most `size` bytes, flushing
+    /// the stream after each of them. An escaped IAC byte is never split from its double, so
+    /// `size` is at least 2. This helps with servers which disconnect clients sending large
+    /// bursts. There is no limit (`None`) by default.
+    pub fn set_max_write_chunk(&mut self, size: Option<usize>) {
+        self.max_write_chunk = size.map(|size| size.max(2));
+    }
+
+    /// Writes text to the remote host, encoded with the encoding set by
+    /// [`Telnet::set_output_encoding`].
+    /// Like [`Telnet::write`], it will double any IAC byte.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::Telnet;
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// connection.write_str("look\r\n").expect("Write Error");
+    /// ```
+    ///
+    /// # Errors
+    /// - Write to stream fails
+    pub fn write_str(&mut self, text: &str) -> io::Result<usize> {
+        let data = self.output_encoding.encode(text).into_owned();
+        self.write(&data)
+    }
+
+    /// Sets the encoding used by [`Telnet::write_str`]. The default is [`encoding::Utf8`].
+    ///
+    /// This is the same as [`Telnet::set_output_encoding`].
+    pub fn set_encoding(&mut self, encoding: Box<dyn encoding::Encoding>) {
+        self.set_output_encoding(encoding);
+    }
+
+    /// Sets the encoding used by [`Telnet::write_str`]. The default is [`encoding::Utf8`].
+    ///
+    /// It is independent of the input encoding, as some hosts send Latin-1 but expect UTF-8.
+    pub fn set_output_encoding(&mut self, encoding: Box<dyn encoding::Encoding>) {
+        self.output_encoding = encoding;
+    }
+
+    /// Sets the encoding used by [`Telnet::decode_input`]. The default is [`encoding::Utf8`].
+    ///
+    /// The bytes of a character left incomplete by the previous encoding are dropped.
+    pub fn set_input_encoding(&mut self, encoding: Box<dyn encoding::Encoding>) {
+        self.input_encoding = encoding;
+        self.input_pending.clear();
+    }
+
+    /// Decodes the payload of an [`Event::Data`] to text, with the encoding set by
+    /// [`Telnet::set_input_encoding`].
+    ///
+    /// A multibyte character split between two reads is kept until the next call completes it,
+    /// so the payloads must be decoded in the order they were received.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::{Event, Telnet};
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// if let Event::Data(data) = connection.read().expect("Read Error") {
+    ///     print!("{}", connection.decode_input(&data));
+    /// }
+    /// ```
+    pub fn decode_input(&mut self, data: &[u8]) -> String {
+        self.input_encoding.decode(data, &mut self.input_pending)
+    }
+
+    /// Writes out any buffered data to the remote host.
+    ///
+    /// This is only needed with [`Telnet::from_stream_buffered`], or with a stream which buffers
+    /// writes itself.
+    ///
+    /// # Errors
+    /// - Write to stream fails
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_backlog()?;
+        self.stream.flush()
+    }
+
+    /// Groups the following writes into as few packets as possible, until [`Telnet::uncork`].
+    ///
+    /// This is useful to send a burst of negotiations together with a first command. Streams
+    /// which support it, such as a [`TcpStream`] on Linux with `TCP_CORK`, hold back partial
+    /// packets themselves. With other streams, the writes are buffered and written at once by
+    /// [`Telnet::uncork`]. Output is not corked by default.
+    ///
+    /// # Errors
+    /// - Setting the socket option fails
+    pub fn cork(&mut self) -> io::Result<()> {
+        if self.corked || self.cork_buffer.is_some() {
+            return Ok(());
+        }
+        match self.stream.set_cork(true) {
+            Ok(()) => self.corked = true,
+            Err(e) if e.kind() == ErrorKind::Unsupported => self.cork_buffer = Some(Vec::new()),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Sends the writes held back since [`Telnet::cork`].
+    ///
+    /// # Errors
+    /// - Write to stream fails
+    /// - Setting the socket option fails
+    pub fn uncork(&mut self) -> io::Result<()> {
+        if let Some(corked) = self.cork_buffer.take() {
+            if let Err((written, e)) = self.write_outbound(&corked) {
+                // Still corked, with what was not written
+                self.cork_buffer = Some(corked[written..].to_vec());
+                return Err(e);
+            }
+            self.stream.flush()?;
+        }
+        if std::mem::take(&mut self.corked) {
+            self.stream.set_cork(false)?;
+        }
+        Ok(())
+    }
+
+    /// Writes bytes to the remote host as they are, without doubling any IAC byte.
+    ///
+    /// This sends frames built by hand, for example with [`format::SubnegotiationBuilder`]. The
+    /// bytes must form valid telnet, or the remote host will misread what follows.
+    ///
+    /// Like every write, negotiation and subnegotiation, `buf` is written whole, after everything
+    /// issued before it. If a nonblocking stream would block, the bytes it did not take are kept
+    /// and written first by the next write, read or [`Telnet::flush`], and `Ok` is returned, so
+    /// they must not be sent again. [`Telnet::flush`] returns the error while bytes are still
+    /// kept. Nothing can be written in the middle of a frame. An expired write timeout is
+    /// returned as an error.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::{format::SubnegotiationBuilder, Telnet, TelnetOption};
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// // IAC SB TTYPE IS "xterm" IAC SE
+    /// let frame = SubnegotiationBuilder::new(TelnetOption::TTYPE)
+    ///     .byte(0)
+    ///     .bytes(b"xterm")
+    ///     .build();
+    /// connection.write_raw(&frame).expect("Write Error");
+    /// ```
+    ///
+    /// # Errors
+    /// - Write to stream fails
+    pub fn write_raw(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.write_frame(buf).map_err(|(_, e)| e)
+    }
+
+    // Write `buf` as `write_raw` does, keeping track of the activity. Returns with the error how
+    // much of it was written.
+    fn write_frame(&mut self, buf: &[u8]) -> Result<(), (usize, io::Error)> {
+        match self.cork_buffer {
+            Some(ref mut corked) => corked.extend_from_slice(buf),
+            None => self.write_outbound(buf)?,
+        }
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    // Write `buf` after the bytes left by earlier writes. If the stream would block, what it did
+    // not take is kept to be written first next time, so that a frame is never split by another,
+    // and `buf` counts as written. On any other error, returns how much of `buf` was written.
+    fn write_outbound(&mut self, buf: &[u8]) -> Result<(), (usize, io::Error)> {
+        match self.write_backlog() {
+            Ok(()) => {}
+            Err(e) if self.is_write_blocked(&e) => {
+                self.outbound.extend_from_slice(buf);
+                return Ok(());
+            }
+            Err(e) => return Err((0, e)),
+        }
+
+        if let Err((written, e)) = write_until_blocked(&mut *self.stream, buf) {
+            if !self.is_write_blocked(&e) {
+                return Err((written, e));
+            }
+            self.outbound.extend_from_slice(&buf[written..]);
+        }
+        Ok(())
+    }
+
+    // Write the bytes left by earlier writes before waiting for a reply to them. If the stream
+    // still would block, they are kept for later.
+    fn send_backlog(&mut self) -> io::Result<()> {
+        match self.write_backlog() {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+
+    // Whether a failed write only has to wait for the stream. A stream put in blocking mode by a
+    // read would block when its write timeout expires, which is an error like `TimedOut`.
+    fn is_write_blocked(&self, e: &io::Error) -> bool {
+        e.kind() == ErrorKind::WouldBlock && self.stream_nonblocking != Some(false)
+    }
+
+    // Write the bytes left by earlier writes. What the stream did not take is kept on any error,
+    // as the data before it was reported as sent.
+    fn write_backlog(&mut self) -> io::Result<()> {
+        if self.outbound.is_empty() {
+            return Ok(());
+        }
+        let mut queued = std::mem::take(&mut self.outbound);
+        match write_until_blocked(&mut *self.stream, &queued) {
+            Ok(()) => {
+                // Kept for its capacity
+                queued.clear();
+                self.outbound = queued;
+                Ok(())
+            }
+            Err((written, e)) => {
+                queued.drain(..written);
+                self.outbound = queued;
+                Err(e)
+            }
+        }
+    }
+
+    /// Sends a single-byte telnet command, `IAC <command>`, as it is.
+    ///
+    /// This is for the commands which take no option, such as NOP (241), GA (249), EOR (239) or
+    /// AYT (246). Negotiations and subnegotiations have their own methods.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::Telnet;
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// // IAC NOP, as a keep-alive
+    /// connection.send_command(241).expect("Write Error");
+    /// ```
+    ///
+    /// # Errors
+    /// - Write to stream fails
+    pub fn send_command(&mut self, command: u8) -> io::Result<()> {
+        self.write_raw(&[BYTE_IAC, command])
+    }
+
+    /// Sends the Interrupt Process command (`IAC IP`), usually mapped to Ctrl-C.
+    ///
+    /// # Errors
+    /// - Write to stream fails
+    pub fn send_interrupt_process(&mut self) -> io::Result<()> {
+        self.write_raw(&[BYTE_IAC, BYTE_IP])
+    }
+
+    /// Sends the Abort Output command (`IAC AO`), usually mapped to Ctrl-O.
+    ///
+    /// # Errors
+    /// - Write to stream fails
+    pub fn send_abort_output(&mut self) -> io::Result<()> {
+        self.write_raw(&[BYTE_IAC, BYTE_AO])
+    }
+
+    /// Sends the Erase Line command (`IAC EL`), usually mapped to Ctrl-U.
+    ///
+    /// # Errors
+    /// - Write to stream fails
+    pub fn send_erase_line(&mut self) -> io::Result<()> {
+        self.write_raw(&[BYTE_IAC, BYTE_EL])
+    }
+
+    /// Negotiates a telnet option with the remote host.
+    ///
+    /// Nothing is sent if the option is already in the requested state, or if the same request is
+    /// still waiting for a reply, so that repeated calls do not confuse the remote host.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::{Telnet, Action, TelnetOption};
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// connection.negotiate(&Action::Will, TelnetOption::Echo);
+    /// ```
+    ///
+    /// # Errors
+    /// - [`TelnetError::NegotiationErr`] if negotiation fails
+    /// - [`TelnetError::InvalidNegotiation`] if rejected in strict mode, see
+    ///   [`Telnet::set_strict_negotiation`]
+    pub fn negotiate(&mut self, action: &Action, opt: TelnetOption) -> Result<(), TelnetError> {
+        let enables = matches!(action, Action::Will | Action::Do);
+        if self.strict_negotiation && enables && matches!(opt, TelnetOption::UnknownOption(_)) {
+            return Err(TelnetError::InvalidNegotiation(*action, opt));
+        }
+        self.send_negotiation(*action, opt)
+    }
+
+    // Send a negotiation, unless it is redundant
+    fn send_negotiation(&mut self, action: Action, opt: TelnetOption) -> Result<(), TelnetError> {
+        if self.options.is_redundant(action, opt) {
+            return Ok(());
+        }
+
+        // A frame kept for a blocked stream is still sent, so it is recorded as well
+        let buf = format::negotiation(action, opt);
+        self.write_raw(&buf).or(Err(NegotiationErr))?;
+        self.options.sent(action, opt);
+        self.forget_window_size(opt);
+        self.negotiation_log
+            .push(LoggedNegotiation::Negotiation(Direction::Sent, action, opt));
+        if opt == TelnetOption::SUPDUP && self.options.is_enabled(opt) {
+            self.passthrough = true;
+        }
+        Ok(())
+    }
+
+    /// Negotiates an option given by its byte, for options which [`TelnetOption`] does not name.
+    ///
+    /// This sends `IAC <action> <option_byte>`, and is tracked the same way as
+    /// [`Telnet::negotiate`], but is never rejected by strict mode.
+    ///
+    /// # Errors
+    /// - [`TelnetError::NegotiationErr`] if negotiation fails
+    pub fn negotiate_raw(&mut self, action: &Action, option_byte: u8) -> Result<(), TelnetError> {
+        self.send_negotiation(*action, TelnetOption::parse(option_byte))
+    }
+
+    /// Asks the remote host to log us off, with the Logout option (RFC 727).
+    ///
+    /// This sends `IAC DO LOGOUT`, as the client. A server which agrees answers `WILL LOGOUT`, see
+    /// [`Telnet::is_logout_acknowledged`], and then closes the connection. The other way around, a
+    /// server about to log the client off on its own, for example after a long idle time, sends
+    /// `WILL LOGOUT`, to which the client may answer `DO LOGOUT` with this method.
+    ///
+    /// # Errors
+    /// - [`TelnetError::NegotiationErr`] if negotiation fails
+    pub fn request_logout(&mut self) -> Result<(), TelnetError> {
+        self.negotiate(&Action::Do, TelnetOption::Logout)
+    }
+
+    /// Whether the remote host agreed to log us off, with `WILL LOGOUT` as the reply to
+    /// [`Telnet::request_logout`].
+    pub fn is_logout_acknowledged(&self) -> bool {
+        self.options.is_enabled_remotely(TelnetOption::Logout)
+    }
+
+    /// Returns the last negotiations and subnegotiations sent and received, oldest first.
+    ///
+    /// This helps investigating a remote host which negotiates oddly. Nothing is recorded unless
+    /// enabled with [`Telnet::set_negotiation_log_capacity`]. Subnegotiations sent with
+    /// [`Telnet::subnegotiate_from_reader`] are not recorded.
+    pub fn negotiation_log(&self) -> &VecDeque<LoggedNegotiation> {
+        self.negotiation_log.entries()
+    }
+
+    /// Sets how many entries [`Telnet::negotiation_log`] keeps, the oldest ones being dropped
+    /// first. It is 0 by default, which disables the log.
+    pub fn set_negotiation_log_capacity(&mut self, capacity: usize) {
+        self.negotiation_log.set_capacity(capacity);
+    }
+
+    /// Lists the negotiations sent with [`Telnet::negotiate`] which the remote host has not
+    /// replied to yet.
+    ///
+    /// An entry is removed once the reply is processed by one of the read methods. This can be
+    /// used to detect a remote host which never answers.
+    pub fn pending_negotiations(&self) -> Vec<(Action, TelnetOption)> {
+        self.options.pending()
+    }
+
+    /// Send data for sub-negotiation with the remote host.
+    ///
+    /// The whole frame (`IAC SB <option> <data> IAC SE`) is built in one buffer, with any IAC
+    /// byte in `data` doubled, then written at once and flushed. Returns the number of bytes
+    /// written to the stream.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::{Telnet, Action, TelnetOption};
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// connection.negotiate(&Action::Do, TelnetOption::TTYPE);
+    /// let data: [u8; 1] = [1];
+    /// connection.subnegotiate(TelnetOption::TTYPE, &data);
+    /// ```
+    ///
+    /// # Errors
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn subnegotiate(&mut self, opt: TelnetOption, data: &[u8]) -> Result<usize, TelnetError> {
+        let buf = format::sub_negotiation(opt, data);
+        if self.negotiation_log.is_enabled() {
+            self.negotiation_log.push(LoggedNegotiation::Subnegotiation(
+                Direction::Sent,
+                opt,
+                Box::from(data),
+            ));
+        }
+        self.write_raw(&buf)
+            .or(Err(SubnegotiationErr(SubnegotiationType::Data)))?;
+        self.stream
+            .flush()
+            .or(Err(SubnegotiationErr(SubnegotiationType::End)))?;
+
+        Ok(buf.len())
+    }
+
+    /// Enables an option on our side and sends a subnegotiation for it once the remote host agrees.
+    ///
+    /// `IAC WILL <option>` is sent, unless already done. If the option is already enabled, the
+    /// subnegotiation is sent right away. Otherwise it is kept until the read methods process the
+    /// `DO` of the remote host, and dropped if it answers `DONT` instead. Returns whether the
+    /// subnegotiation was sent right away.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::{Telnet, TelnetOption};
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// // 80x24
+    /// connection.enable_and_subnegotiate(TelnetOption::NAWS, &[0, 80, 0, 24]);
+    /// ```
+    ///
+    /// # Errors
+    /// - [`TelnetError::NegotiationErr`] if negotiation fails
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn enable_and_subnegotiate(
+        &mut self,
+        opt: TelnetOption,
+        data: &[u8],
+    ) -> Result<bool, TelnetError> {
+        if self.options.is_enabled_locally(opt) {
+            self.subnegotiate(opt, data)?;
+            return Ok(true);
+        }
+
+        self.negotiate(&Action::Will, opt)?;
+        if self.options.is_enabled_locally(opt) {
+            // The remote host had already asked for it
+            self.subnegotiate(opt, data)?;
+            return Ok(true);
+        }
+        self.deferred_subnegotiations.push((opt, Box::from(data)));
+        Ok(false)
+    }
+
+    /// Sends data for sub-negotiation like [`Telnet::subnegotiate`], but only if the option was
+    /// agreed to.
+    ///
+    /// Sending a subnegotiation for an option which neither side enabled is usually a bug. Use
+    /// [`Telnet::subnegotiate`] to send it anyway.
+    ///
+    /// # Errors
+    /// - [`TelnetError::OptionNotEnabled`] if the option was not agreed to with the remote host
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn subnegotiate_checked(
+        &mut self,
+        opt: TelnetOption,
+        data: &[u8],
+    ) -> Result<usize, TelnetError> {
+        if !self.options.is_enabled(opt) {
+            return Err(OptionNotEnabled(opt));
+        }
+        self.subnegotiate(opt, data)
+    }
+
+    /// Sends a subnegotiation whose data is streamed from `reader`.
+    ///
+    /// Unlike [`Telnet::subnegotiate`], the data is read, escaped and written in chunks, so a large
+    /// payload never needs to be held in memory at once. Returns the number of bytes written to the
+    /// stream.
+    ///
+    /// # Errors
+    /// - Read from `reader` fails
+    /// - Write to stream fails
+    pub fn subnegotiate_from_reader<R: Read>(
+        &mut self,
+        opt: TelnetOption,
+        mut reader: R,
+    ) -> io::Result<usize> {
+        let header = [BYTE_IAC, BYTE_SB, opt.as_byte()];
+        self.write_raw(&header)?;
+        let mut write_size = header.len();
+
+        let mut chunk = [0; 4096];
+        loop {
+            let size = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(size) => size,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let escaped = format::data(&chunk[..size]);
+            self.write_raw(&escaped)?;
+            write_size += escaped.len();
+        }
+
+        let footer = [BYTE_IAC, BYTE_SE];
+        self.write_raw(&footer)?;
+        self.stream.flush()?;
+        Ok(write_size + footer.len())
+    }
+
+    /// Sends MSSP status variables to the remote host, as a MUD server answering an MSSP request.
+    ///
+    /// Each entry is the name of a variable with its values, as a variable can have several.
+    ///
+    /// # Examples
+    /// ```rust,should_panic
+    /// use telnet::Telnet;
+    ///
+    /// let mut connection = Telnet::connect(("127.0.0.1", 23), 256)
+    ///         .expect("Couldn't connect to the server...");
+    /// connection.send_mssp(&[("NAME", &["My MUD"]), ("PORT", &["4000", "4001"])]);
+    /// ```
+    ///
+    /// # Errors
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn send_mssp(&mut self, vars: &[(&str, &[&str])]) -> Result<usize, TelnetError> {
+        self.subnegotiate(TelnetOption::MSSP, &subneg::encode_mssp(vars))
+    }
+
+    /// Replies to the authentication types offered by the remote host with an Authentication `IS`.
+    ///
+    /// [`auth::AuthReply::Reject`] refuses every offered type, for a client which supports
+    /// none of them but has already agreed to the option.
+    ///
+    /// # Errors
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn send_auth_reply(&mut self, reply: &auth::AuthReply) -> Result<usize, TelnetError> {
+        self.subnegotiate(TelnetOption::Authentication, &reply.to_bytes())
+    }
+
+    /// Refuses the Encryption option (RFC 2946) in both directions.
+    ///
+    /// `WONT ENCRYPT` and `DONT ENCRYPT` are sent where the remote host asked for the option, or
+    /// where it is enabled, so that the session goes on in clear. This suits a client which
+    /// recognizes an [`Event::Encryption`] but implements no cipher.
+    ///
+    /// # Errors
+    /// - [`TelnetError::NegotiationErr`] if negotiation fails
+    pub fn refuse_encryption(&mut self) -> Result<(), TelnetError> {
+        self.negotiate(&Action::Wont, TelnetOption::Encryption)?;
+        self.negotiate(&Action::Dont, TelnetOption::Encryption)
+    }
+
+    /// Sends an LFLOW (remote flow control) command to the remote host.
+    ///
+    /// # Errors
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn send_lflow(&mut self, cmd: lflow::LFlowCmd) -> Result<usize, TelnetError> {
+        self.subnegotiate(TelnetOption::LFLOW, &[cmd.as_byte()])
+    }
+
+    /// Sends a banner to the remote host with an OUTMRK (output marking) subnegotiation, as a
+    /// server.
+    ///
+    /// The user side answers with [`outmrk::OutputMarking::Ack`] or
+    /// [`outmrk::OutputMarking::Nak`], which the read methods return as
+    /// [`Event::OutputMarking`].
+    ///
+    /// # Errors
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn send_output_marking(
+        &mut self,
+        marker: outmrk::Marker,
+        banner: &str,
+    ) -> Result<usize, TelnetError> {
+        self.subnegotiate(TelnetOption::OUTMRK, &outmrk::encode(marker, banner))
+    }
+
+    /// Sends the window size to the remote host with a NAWS subnegotiation, if it changed.
+    ///
+    /// Nothing is sent if NAWS has not been agreed to with the remote host, or if the size is
+    /// the same as the last one sent. This makes it cheap to call on every window resize. Returns
+    /// whether the size was sent.
+    ///
+    /// # Errors
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn update_window_size(&mut self, width: u16, height: u16) -> Result<bool, TelnetError> {
+        if !self.options.is_enabled_locally(TelnetOption::NAWS)
+            || self.window_size == Some((width, height))
+        {
+            return Ok(false);
+        }
+
+        let [w1, w2] = width.to_be_bytes();
+        let [h1, h2] = height.to_be_bytes();
+        self.subnegotiate(TelnetOption::NAWS, &[w1, w2, h1, h2])?;
+        self.window_size = Some((width, height));
+        Ok(true)
+    }
+
+    /// Sets the values used to answer the requests of the remote host automatically.
+    ///
+    /// While processing the received data, a `SEND` subnegotiation of TTYPE, TSPEED or
+    /// NEW-ENVIRON is answered with an `IS` subnegotiation if the policy has a value for that
+    /// option, and a `DO NAWS` is answered with `WILL NAWS` and the window size if the policy has
+    /// one. The requests are still returned as events. Options without a configured value are
+    /// never answered. A `SEND` is only answered once we agreed to use the option with `WILL`,
+    /// unless [`SubnegotiationPolicy::answer_unsolicited`](subneg::SubnegotiationPolicy::answer_unsolicited)
+    /// is set.
+    pub fn set_subnegotiation_policy(&mut self, policy: subneg::SubnegotiationPolicy) {
+        self.subnegotiation_policy = policy;
+    }
+
+    /// Answers TTYPE `SEND` requests with the terminal type `name`.
+    pub fn set_terminal_type(&mut self, name: &str) {
+        self.subnegotiation_policy.terminal_type = Some(name.to_string());
+    }
+
+    /// Answers TSPEED `SEND` requests with the transmit and receive speeds.
+    pub fn set_terminal_speed(&mut self, transmit: u32, receive: u32) {
+        self.subnegotiation_policy.terminal_speed = Some((transmit, receive));
+    }
+
+    /// Answers NEW-ENVIRON `SEND` requests with the given variables.
+    pub fn set_environment(&mut self, vars: &[(&str, &str)]) {
+        self.subnegotiation_policy.environment = vars
+            .iter()
+            .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+            .collect();
+    }
+
+    /// Answers `DO NAWS` with the window size, and sends the new size right away if NAWS is
+    /// already enabled.
+    ///
+    /// # Errors
+    /// - [`TelnetError::SubnegotiationErr`] if subnegotiation fails
+    pub fn set_window_size(&mut self, width: u16, height: u16) -> Result<(), TelnetError> {
+        self.subnegotiation_policy.window_size = Some((width, height));
+        self.update_window_size(width, height)?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn process(&mut self) {
+        // Nothing was read, which must not end a pending unknown command
+        if self.buffered_size == 0 {
+            return;
+        }
+
+        if self.auto_detect {
+            self.detect_raw_stream();
+        }
+
+        if self.passthrough {
+            self.push_buffered_data(0, self.buffered_size);
+            return;
+        }
+
+        let mut data_start = 0;
+
+        let mut current = 0;
+        while current < self.buffered_size {
+            // Every byte up to the next IAC is data, so skip straight to it
+            if let ProcessState::NormalData = self.state {
+                let rest = &self.buffer[current..self.buffered_size];
+                match memchr::memchr(BYTE_IAC, rest) {
+                    Some(offset) => current += offset,
+                    None => break,
+                }
+            }
+
+            // Gather a byte
+            let byte = self.buffer[current];
+
+            match self.state {
+                ProcessState::NormalData => {
+                    // The following bytes will be commands
+                    // Update the state
+                    self.state = ProcessState::IAC;
+
+                    // Send the data before this byte
+                    self.push_buffered_data(data_start, current);
+                }
+
+                // Telnet Commands
+                ProcessState::IAC => {
+                    match byte {
+                        // Negotiation Commands
+                        BYTE_WILL => self.state = ProcessState::Will,
+                        BYTE_WONT => self.state = ProcessState::Wont,
+                        BYTE_DO => self.state = ProcessState::Do,
+                        BYTE_DONT => self.state = ProcessState::Dont,
+                        // Subnegotiation
+                        BYTE_SB => self.state = ProcessState::SB,
+                        // Escaping
+                        BYTE_IAC => {
+                            // The second IAC is kept as data, in place, so the escaped byte
+                            // needs no copy even when the pair is split across reads
+                            self.state = ProcessState::NormalData;
+                            data_start = current;
+                        }
+                        // Unknown IAC commands
+                        _ if self.unknown_iac_handler.is_some() => {
+                            self.state = ProcessState::NormalData;
+                            data_start = current + 1;
+
+                            if let Some(mut handler) = self.unknown_iac_handler.take() {
+                                handler(byte, self);
+                                // Unless the handler replaced itself
+                                self.unknown_iac_handler.get_or_insert(handler);
+                            }
+                            if self.command_params > 0 {
+                                self.state = ProcessState::CommandParams;
+                                self.sb_buffer.clear();
+                                self.sb_buffer.push(byte);
+                            }
+                        }
+                        _ if self.raw_commands => {
+                            self.state = ProcessState::RawCommand;
+                            self.sb_buffer.clear();
+                            self.sb_buffer.push(byte);
+                        }
+                        _ => {
+                            self.state = ProcessState::NormalData;
+                            data_start = current + 1;
+                            self.push_event(Event::UnknownIAC(byte));
+                        }
+                    }
+                }
+
+                // Unknown command and the bytes following it
+                ProcessState::RawCommand => {
+                    if byte == BYTE_IAC {
+                        self.push_raw_command();
+                        self.state = ProcessState::IAC;
+                    } else {
+                        self.sb_buffer.push(byte);
+                    }
+                }
+
+                // Parameters of an unknown command, as requested by its handler
+                ProcessState::CommandParams => {
+                    self.sb_buffer.push(byte);
+                    self.command_params -= 1;
+                    if self.command_params == 0 {
+                        self.push_raw_command();
+                        self.state = ProcessState::NormalData;
+                        data_start = current + 1;
+                    }
+                }
+
+                // Negotiation
+                ProcessState::Will | ProcessState::Wont | ProcessState::Do | ProcessState::Dont => {
+                    let opt = TelnetOption::parse(byte);
+
+                    let mut outcome = None;
+                    if let Some(action) = self.state.action() {
+                        if self.raw_frames {
+                            let frame = format::negotiation(action, opt);
+                            self.push_negotiation_event(Event::RawFrame(Box::new(frame)));
+                        }
+                        outcome = self.options.received(action, opt);
+                        self.forget_window_size(opt);
+                        self.negotiation_log.push(LoggedNegotiation::Negotiation(
+                            Direction::Received,
+                            action,
+                            opt,
+                        ));
+                        self.auto_negotiate(action, opt);
+                        self.auto_respond(action, opt);
+                        self.send_deferred_subnegotiations(opt);
+                    }
+
+                    match self.state {
+                        ProcessState::Will => {
+                            self.push_negotiation_event(Event::Negotiation(Action::Will, opt));
+                        }
+                        ProcessState::Wont => {
+                            self.push_negotiation_event(Event::Negotiation(Action::Wont, opt));
+                        }
+                        ProcessState::Do => {
+                            self.push_negotiation_event(Event::Negotiation(Action::Do, opt));
+                        }
+                        ProcessState::Dont => {
+                            self.push_negotiation_event(Event::Negotiation(Action::Dont, opt));
+                        }
+                        _ => {} // Do nothing
+                    }
+                    match outcome {
+                        Some(true) => self.push_negotiation_event(Event::OptionEnabled(opt)),
+                        Some(false) => self.push_negotiation_event(Event::OptionRefused(opt)),
+                        None => {}
+                    }
+
+                    self.state = ProcessState::NormalData;
+                    data_start = current + 1;
+
+                    // The rest is not telnet anymore
+                    if opt == TelnetOption::SUPDUP && self.options.is_enabled(opt) {
+                        self.passthrough = true;
+                        break;
+                    }
+                }
+
+                // Start subnegotiation
+                ProcessState::SB => {
+                    let opt = TelnetOption::parse(byte);
+                    self.state = ProcessState::SBData(opt);
+                    self.sb_buffer.clear();
+                }
+
+                // Subnegotiation's data
+                ProcessState::SBData(opt) => {
+                    if byte == BYTE_IAC {
+                        self.state = ProcessState::SBDataIAC(opt);
+                    } else if byte == BYTE_SE
+                        && self
+                            .lenient_se_after
+                            .is_some_and(|min_len| self.sb_buffer.len() >= min_len)
+                    {
+                        // A server which lost track of the escaping ended it without IAC
+                        self.push_event(Event::Error(BareSubnegotiationEnd(opt)));
+                        self.state = ProcessState::NormalData;
+                        data_start = current + 1;
+                        if self.end_subnegotiation(opt, current + 1) {
+                            break;
+                        }
+                    } else {
+                        self.push_subnegotiation_byte(opt, byte);
+                    }
+                }
+
+                // IAC inside Subnegotiation's data
+                ProcessState::SBDataIAC(opt) => {
+                    match byte {
+                        // The end of subnegotiation
+                        BYTE_SE => {
+                            // Update state
+                            self.state = ProcessState::NormalData;
+                            data_start = current + 1;
+
+                            // Return the option
+                            if self.end_subnegotiation(opt, current + 1) {
+                                break;
+                            }
+                        }
+                        // Escaping
+                        BYTE_IAC => {
+                            // Update the state
+                            self.state = ProcessState::SBData(opt);
+
+                            // Add escaped IAC
+                            self.push_subnegotiation_byte(opt, BYTE_IAC);
+                        }
+                        _ if self.strict_subnegotiation => {
+                            let data = std::mem::take(&mut self.sb_buffer).into_boxed_slice();
+                            self.push_event(Event::Error(MalformedSubnegotiation(opt, data)));
+
+                            // Skip the rest of the subnegotiation
+                            self.state = ProcessState::SBDiscard;
+                        }
+                        b => {
+                            self.push_event(Event::Error(UnexpectedByte(b)));
+
+                            // Update the state
+                            self.state = ProcessState::SBData(opt);
+                        }
+                    }
+                }
+
+                // The rest of a malformed subnegotiation
+                ProcessState::SBDiscard => {
+                    if byte == BYTE_IAC {
+                        self.state = ProcessState::SBDiscardIAC;
+                    }
+                }
+                ProcessState::SBDiscardIAC => {
+                    if byte == BYTE_SE {
+                        self.state = ProcessState::NormalData;
+                        data_start = current + 1;
+                    } else {
+                        self.state = ProcessState::SBDiscard;
+                    }
+                }
+            }
+
+            current += 1;
+        }
+
+        // Send the rest of the data. Any command left unfinished is completed by the next read,
+        // except for an unknown command, which ends with the read.
+        match self.state {
+            ProcessState::NormalData => self.push_buffered_data(data_start, self.buffered_size),
+            ProcessState::RawCommand => {
+                self.push_raw_command();
+                self.state = ProcessState::NormalData;
+            }
+            _ => {}
+        }
+        if self.coalesce.is_none() {
+            self.flush_coalesced();
+        }
+    }
+
+    // Look for an IAC in what is left of the first bytes, switching to passthrough without one.
+    // Until then every byte was data, so the bytes read so far need no parsing either way.
+    fn detect_raw_stream(&mut self) {
+        let rest = AUTO_DETECT_LEN - self.auto_detect_seen;
+        let window = &self.buffer[..self.buffered_size.min(rest)];
+        if window.contains(&BYTE_IAC) {
+            self.auto_detect = false;
+        } else if self.buffered_size >= rest {
+            self.auto_detect = false;
+            self.passthrough = true;
+        } else {
+            self.auto_detect_seen += self.buffered_size;
+        }
+    }
+
+    // Queue the subnegotiation collected so far, which ended right before `end`. Returns whether
+    // MCCP2 starts, leaving the rest of the buffer to the stream.
+    #[cfg_attr(not(feature = "zcstream"), allow(unused_variables))]
+    fn end_subnegotiation(&mut self, opt: TelnetOption, end: usize) -> bool {
+        let data = std::mem::take(&mut self.sb_buffer).into_boxed_slice();
+        if self.raw_frames {
+            let frame = format::sub_negotiation(opt, &data);
+            self.push_event(Event::RawFrame(frame.into_boxed_slice()));
+        }
+        if self.negotiation_log.is_enabled() {
+            self.negotiation_log.push(LoggedNegotiation::Subnegotiation(
+                Direction::Received,
+                opt,
+                data.clone(),
+            ));
+        }
+        self.push_subnegotiation(opt, data);
+
+        // Everything after it is compressed
+        #[cfg(feature = "zcstream")]
+        if self.auto_mccp && opt == TelnetOption::Compress2 && self.options.is_enabled(opt) {
+            let rest = &self.buffer[end..self.buffered_size];
+            self.stream.begin_zlib_with_input(rest);
+            self.buffered_size = end;
+            return true;
+        }
+        false
+    }
+
+    // Add a byte to the subnegotiation being received, dropping the subnegotiation once it
+    // exceeds its limit
+    fn push_subnegotiation_byte(&mut self, opt: TelnetOption, byte: u8) {
+        let limit = self
+            .subnegotiation_limits
+            .get(&opt.as_byte())
+            .copied()
+            .or(self.max_subnegotiation_len);
+        match limit {
+            Some(limit) if self.sb_buffer.len() >= limit => {
+                self.sb_buffer.clear();
+                self.push_event(Event::Error(SubnegotiationTooLong(opt, limit)));
+                self.state = ProcessState::SBDiscard;
+            }
+            _ => self.sb_buffer.push(byte),
+        }
+    }
+
+    // Forget the last window size sent once NAWS is no longer enabled, so that it is sent again
+    // when NAWS is enabled next
+    fn forget_window_size(&mut self, opt: TelnetOption) {
+        if opt == TelnetOption::NAWS && !self.options.is_enabled_locally(opt) {
+            self.window_size = None;
+        }
+    }
+
+    // Answer `WILL COMPRESS2` if MCCP2 is handled automatically, and `DO NAWS` if the policy has
+    // a window size
+    fn auto_negotiate(&mut self, action: Action, opt: TelnetOption) {
+        #[cfg(feature = "zcstream")]
+        if self.auto_mccp && action == Action::Will && opt == TelnetOption::Compress2 {
+            if let Err(err) = self.negotiate(&Action::Do, opt) {
+                self.push_event(Event::Error(err));
+            }
+            return;
+        }
+
+        let Some((width, height)) = self.subnegotiation_policy.window_size else {
+            return;
+        };
+        if action != Action::Do || opt != TelnetOption::NAWS {
+            return;
+        }
+
+        // The size is sent again, as the remote host asked for it
+        self.window_size = None;
+        let result = self
+            .negotiate(&Action::Will, opt)
+            .and_then(|()| self.update_window_size(width, height));
+        if let Err(err) = result {
+            self.push_event(Event::Error(err));
+        }
+    }
+
+    // Answer a request to enable an option which is still unanswered, if auto-respond is set
+    fn auto_respond(&mut self, action: Action, opt: TelnetOption) {
+        let Some(accepted) = &self.auto_respond else {
+            return;
+        };
+        if !self.options.is_awaiting_reply(action, opt) {
+            return;
+        }
+
+        let reply = if accepted.contains(&opt) {
+            action.accept()
+        } else {
+            action.refuse()
+        };
+        if let Some(reply) = reply {
+            if let Err(err) = self.negotiate(&reply, opt) {
+                self.push_event(Event::Error(err));
+            }
+        }
+    }
+
+    // The number of bytes of data sent when the stream took `written`, the start of their escaped
+    // form. If it failed between an IAC byte and its escape, the escape is kept to be written
+    // first next time, so that the IAC byte counts as sent.
+    #[allow(clippy::naive_bytecount)]
+    fn complete_escape(&mut self, written: &[u8]) -> usize {
+        if written.iter().filter(|&&byte| byte == BYTE_IAC).count() % 2 == 1 {
+            self.outbound.push(BYTE_IAC);
+        }
+        unescaped_len(written)
+    }
+
+    // Write `data` escaped, in chunks of at most `max_chunk` bytes, each flushed on its own
+    fn write_chunked(&mut self, data: &[u8], max_chunk: usize) -> io::Result<usize> {
+        let mut chunk = vec![0; max_chunk.min(format::escaped_len(data))];
+        let mut rest = data;
+        while !rest.is_empty() {
+            let len = format::escape_into(rest, &mut chunk);
+            let sent = data.len() - rest.len();
+            if let Err((written, e)) = self.write_frame(&chunk[..len]) {
+                let chunk_sent = self.complete_escape(&chunk[..written]);
+                return partial_write(sent + chunk_sent, e);
+            }
+            let chunk_len = unescaped_len(&chunk[..len]);
+            if let Err(e) = self.stream.flush() {
+                return partial_write(sent + chunk_len, e);
+            }
+            rest = &rest[chunk_len..];
+        }
+        Ok(data.len())
+    }
+
+    // Set the stream mode, unless it is already set. Some streams make a system call every time.
+    fn set_stream_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        if self.stream_nonblocking != Some(nonblocking) {
+            self.stream_nonblocking = None;
+            self.stream.set_nonblocking(nonblocking)?;
+            self.stream_nonblocking = Some(nonblocking);
+        }
+        Ok(())
+    }
+
+    fn set_stream_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        if self.stream_read_timeout != Some(timeout) {
+            self.stream_read_timeout = None;
+            self.stream.set_read_timeout(timeout)?;
+            self.stream_read_timeout = Some(timeout);
+        }
+        Ok(())
+    }
+
+    // Put events back in front of anything read meanwhile
+    fn requeue_front(&mut self, events: Vec<Event>) {
+        self.event_queue.requeue_front(events);
+    }
+
+    // Send the subnegotiations waiting for the option to be enabled, or drop them if it was refused
+    fn send_deferred_subnegotiations(&mut self, opt: TelnetOption) {
+        if !self.deferred_subnegotiations.iter().any(|(o, _)| *o == opt) {
+            return;
+        }
+
+        let enabled = self.options.is_enabled_locally(opt);
+        if !enabled && self.options.is_redundant(Action::Will, opt) {
+            // Still waiting for the reply
+            return;
+        }
+
+        let (ready, waiting) = std::mem::take(&mut self.deferred_subnegotiations)
+            .into_iter()
+            .partition(|(o, _)| *o == opt);
+        self.deferred_subnegotiations = waiting;
+        if enabled {
+            for (_, data) in ready {
+                if let Err(err) = self.subnegotiate(opt, &data) {
+                    self.push_event(Event::Error(err));
+                }
+            }
+        }
+    }
+
+    // Read until an event is queued, as `read` does. Returns the event to return instead of the
+    // queued ones, when the wait ended without any.
+    fn fill_queue(&mut self) -> io::Result<Option<Event>> {
+        if let Some(timeout) = self.default_read_timeout {
+            return self.fill_queue_timeout(timeout);
+        }
+
+        while self.event_queue.is_empty() {
+            // While data is being coalesced, wait no longer than the coalescing window
+            let coalesce_wait = match self.coalesce_deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        self.flush_coalesced();
+                        continue;
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+
+            // Nor longer than the rest of the idle timeout
+            let idle_wait = match self.idle_timeout {
+                Some(timeout) => {
+                    let remaining =
+                        (self.last_data + timeout).saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        // Start a new idle period, so the next read waits again
+                        self.last_data = Instant::now();
+                        return Ok(Some(Event::Idle));
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+
+            let wait = match (coalesce_wait, idle_wait) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+
+            // Set stream settings
+            self.send_backlog()?;
+            self.stream.flush()?;
+            self.set_stream_nonblocking(false)?;
+            self.set_stream_read_timeout(wait)?;
+
+            // Read bytes to the buffer
+            match self.stream.read(&mut self.buffer) {
+                Ok(size) => self.buffered_size = size,
+                Err(e)
+                    if wait.is_some()
+                        && (e.kind() == ErrorKind::WouldBlock
+                            || e.kind() == ErrorKind::TimedOut) =>
+                {
+                    self.release_cr();
+                    self.flush_coalesced();
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+
+            self.process_or_flush()?;
+        }
+
+        Ok(None)
+    }
+
+    // Read until an event is queued, as `read_timeout` does
+    fn fill_queue_timeout(&mut self, timeout: Duration) -> io::Result<Option<Event>> {
+        if !self.event_queue.is_empty() {
+            return Ok(None);
+        }
+
+        let deadline = Instant::now() + timeout;
+
+        // Set stream settings
+        self.send_backlog()?;
+        self.stream.flush()?;
+        self.set_stream_nonblocking(false)?;
+
+        // Read bytes to the buffer, until a complete event arrives. Depending on the platform,
+        // an expired read timeout is reported as either `WouldBlock` or `TimedOut`, so either
+        // one is only treated as a timeout once the deadline has actually passed. An earlier
+        // one is retried with the remaining time.
+        while self.event_queue.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                self.release_cr();
+                if !self.event_queue.is_empty() {
+                    break;
+                }
+                return Ok(Some(Event::TimedOut));
+            }
+            self.set_stream_read_timeout(Some(remaining))?;
+
+            match self.stream.read(&mut self.buffer) {
+                Ok(size) => self.buffered_size = size,
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    continue
+                }
+                Err(e) => return Err(e),
+            }
+
+            self.process_or_flush()?;
+            self.flush_coalesced();
+        }
+
+        Ok(None)
+    }
+
+    fn take_queued_event(&mut self) -> io::Result<Event> {
+        match self.event_queue.take_event() {
+            Some(Event::Error(err)) if self.errors_as_io_error => Err(err.into()),
+            Some(event) => Ok(event),
+            None => Ok(Event::Error(InternalQueueErr)),
+        }
+    }
+
+    fn push_raw_command(&mut self) {
+        let command = std::mem::take(&mut self.sb_buffer).into_boxed_slice();
+        self.push_event(Event::RawCommand(command));
+    }
+
+    // A read of 0 bytes means the remote host closed the connection. Whatever was held back is
+    // still delivered before reporting it, and a subnegotiation cut off by it is reported.
+    fn process_or_flush(&mut self) -> io::Result<()> {
+        if self.buffered_size == 0 {
+            self.flush_pending();
+            if let ProcessState::SBData(opt) | ProcessState::SBDataIAC(opt) = self.state {
+                let data = std::mem::take(&mut self.sb_buffer).into_boxed_slice();
+                self.push_event(Event::Error(IncompleteSubnegotiation(opt, data)));
+                self.state = ProcessState::NormalData;
+            }
+            if self.event_queue.is_empty() {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "connection closed by the remote host",
+                ));
+            }
+        } else {
+            self.last_data = Instant::now();
+            self.last_activity = self.last_data;
+            self.process();
+        }
+        Ok(())
+    }
+
+    fn push_data(&mut self, data: Box<[u8]>) {
+        let data = if self.bare_cr_policy == BareCrPolicy::Passthrough && !self.cr_pending {
+            data
+        } else {
+            let data = self.bare_cr_policy.apply(&mut self.cr_pending, &data);
+            if data.is_empty() {
+                return;
+            }
+            data.into_boxed_slice()
+        };
+
+        let data = if self.utf8_reassembly {
+            // Prepend the bytes held back from the last read and hold back a new incomplete tail
+            let mut data = if self.utf8_pending.is_empty() {
+                data.into_vec()
+            } else {
+                let mut pending = std::mem::take(&mut self.utf8_pending);
+                pending.extend_from_slice(&data);
+                pending
+            };
+            let tail_len = utf8::incomplete_tail_len(&data);
+            self.utf8_pending = data.split_off(data.len() - tail_len);
+
+            if data.is_empty() {
+                return;
+            }
+            data.into_boxed_slice()
+        } else {
+            data
+        };
+
+        match self.coalesce {
+            Some((size, window)) => {
+                if self.coalesced.is_empty() {
+                    self.coalesce_deadline = Some(Instant::now() + window);
+                }
+                self.coalesced.extend_from_slice(&data);
+                if self.coalesced.len() >= size {
+                    self.flush_coalesced();
+                }
+            }
+            // Held until the end of the read
+            None if self.negotiation_transparent => self.coalesced.extend_from_slice(&data),
+            None => self.event_queue.push_event(Event::Data(data)),
+        }
+    }
+
+    // Queue a negotiation, leaving the data around it to be merged in transparent mode
+    fn push_negotiation_event(&mut self, event: Event) {
+        if self.negotiation_transparent {
+            self.event_queue.push_event(event);
+        } else {
+            self.push_event(event);
+        }
+    }
+
+    // Queue an event other than data, after any data collected before it
+    fn push_event(&mut self, event: Event) {
+        self.flush_coalesced();
+        self.event_queue.push_event(event);
+    }
+
+    // Deliver a CR held back by the bare CR policy once a read timed out or would block, as the
+    // caller waiting for a prompt ending with it would not get it otherwise. The CR is bare as
+    // far as the policy is concerned.
+    fn release_cr(&mut self) {
+        if std::mem::take(&mut self.cr_pending) {
+            self.coalesced.extend(self.bare_cr_policy.bare_cr());
+        }
+        self.flush_coalesced();
+    }
+
+    fn flush_coalesced(&mut self) {
+        self.coalesce_deadline = None;
+        if !self.coalesced.is_empty() {
+            let data = std::mem::take(&mut self.coalesced).into_boxed_slice();
+            self.event_queue.push_event(Event::Data(data));
+        }
+    }
+
+    // Queue a subnegotiation, decoding it for the options with a typed event
+    fn push_subnegotiation(&mut self, opt: TelnetOption, data: Box<[u8]>) {
+        if !self.allow_unsolicited_subnegotiation && !self.options.is_enabled(opt) {
+            self.push_event(Event::Error(UnsolicitedSubnegotiation(opt)));
+            return;
+        }
+
+        let agreed = self.options.is_enabled_locally(opt);
+        let policy = &self.subnegotiation_policy;
+        let reply = (agreed || policy.answer_unsolicited)
+            .then(|| policy.reply(opt, &data))
+            .flatten();
+        if let Some(reply) = reply {
+            if let Err(err) = self.subnegotiate(opt, &reply) {
+                self.push_event(Event::Error(err));
+            }
+        }
+
+        let event = match opt {
+            TelnetOption::EXOPL => match exopl::parse(&data) {
+                Some(ext) => Event::ExtendedOption(ext),
+                None => Event::Subnegotiation(opt, data),
+            },
+            TelnetOption::LFLOW => match lflow::parse(&data) {
+                Some(cmd) => Event::LFlow(cmd),
+                None => Event::Subnegotiation(opt, data),
+            },
+            TelnetOption::OPT3270Regime => match tn3270::parse(&data) {
+                Some(cmd) => Event::Tn3270Regime(cmd),
+                None => Event::Subnegotiation(opt, data),
+            },
+            TelnetOption::TTYPE if self.decode_terminal_type => match subneg::ttype(&data) {
+                Some(subneg::TerminalType::Is(name)) => Event::TerminalType(name),
+                Some(subneg::TerminalType::Send) => Event::TerminalTypeRequested,
+                None => Event::Subnegotiation(opt, data),
+            },
+            TelnetOption::OUTMRK => match outmrk::parse(&data) {
+                Some(marking) => Event::OutputMarking(marking),
+                None => Event::Subnegotiation(opt, data),
+            },
+            TelnetOption::RCTE => match rcte::parse(&data) {
+                Some(cmd) => Event::Rcte(cmd),
+                None => Event::Subnegotiation(opt, data),
+            },
+            TelnetOption::Encryption => match encryption::parse(&data) {
+                Some(cmd) => Event::Encryption(cmd),
+                None => Event::Subnegotiation(opt, data),
+            },
+            _ => Event::Subnegotiation(opt, data),
+        };
+        self.push_event(event);
+    }
+
+    fn push_buffered_data(&mut self, data_start: usize, data_end: usize) {
+        if data_end <= data_start {
+            return;
+        }
+        if self.borrowed_data.is_some() && self.is_data_passed_as_is() {
+            let queued_before = self.event_queue.len();
+            if let Some(ranges) = &mut self.borrowed_data {
+                ranges.push((queued_before, data_start..data_end));
+            }
+            return;
+        }
+        let data = Box::from(&self.buffer[data_start..data_end]);
+        self.push_data(data);
+    }
+
+    // Whether `push_data` would queue the data unchanged, right away
+    fn is_data_passed_as_is(&self) -> bool {
+        self.bare_cr_policy == BareCrPolicy::Passthrough
+            && !self.cr_pending
+            && !self.utf8_reassembly
+            && self.coalesce.is_none()
+            && !self.negotiation_transparent
+    }
+}
+
+// Pass an event to the callback of `read_with`, borrowing the payload of data
+fn pass_event<F: FnMut(EventRef<'_>)>(f: &mut F, event: Event) {
+    match event {
+        Event::Data(data) => f(EventRef::Data(&data)),
+        event => f(EventRef::Other(event)),
+    }
+}
+
+// The number of data bytes in an escaped block. An IAC byte without its escape is counted.
+#[allow(clippy::naive_bytecount)]
+fn unescaped_len(escaped: &[u8]) -> usize {
+    escaped.len() - escaped.iter().filter(|&&byte| byte == BYTE_IAC).count() / 2
+}
+
+// Report a failed write as `Write::write` does: the bytes sent, if any, else the error
+fn partial_write(sent: usize, e: io::Error) -> io::Result<usize> {
+    if sent == 0 {
+        Err(e)
+    } else {
+        Ok(sent)
+    }
+}
+
+// Write all of `buf`, or return how much was written with the error which stopped it
+fn write_until_blocked(stream: &mut TStream, buf: &[u8]) -> Result<(), (usize, io::Error)> {
+    let mut written = 0;
+    while written < buf.len() {
+        match stream.write(&buf[written..]) {
+            Ok(0) => return Err((written, ErrorKind::WriteZero.into())),
+            Ok(size) => written += size,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err((written, e)),
+        }
+    }
+    Ok(())
+}
+
+/// Opens a telnet connection over an already connected [`TcpStream`], with a buffer of 4096 bytes.
+/// See [`Telnet::from_tcp_stream`].
+impl From<TcpStream> for Telnet {
+    fn from(stream: TcpStream) -> Telnet {
+        Telnet::from_tcp_stream(stream, DEFAULT_BUF_SIZE)
+    }
+}
+
+/// Opens a telnet connection over a generic stream, with a buffer of 4096 bytes. See
+/// [`Telnet::from_stream`].
+impl From<Box<TStream>> for Telnet {
+    fn from(stream: Box<TStream>) -> Telnet {
+        Telnet::from_stream(stream, DEFAULT_BUF_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, io::Error, rc::Rc};
+
+    struct MockStream {
+        test_data: Vec<u8>,
+        writes: Rc<RefCell<Vec<Vec<u8>>>>,
+        mode_changes: Rc<std::cell::Cell<usize>>,
+    }
+
+    impl MockStream {
+        fn new(data: Vec<u8>) -> MockStream {
+            MockStream {
+                test_data: data,
+                writes: Rc::new(RefCell::new(Vec::new())),
+                mode_changes: Rc::default(),
+            }
+        }
+
+        // A handle to every buffer passed to `write`, in order
+        fn writes(&self) -> Rc<RefCell<Vec<Vec<u8>>>> {
+            Rc::clone(&self.writes)
+        }
+
+        // A handle to the number of calls to `set_nonblocking` and `set_read_timeout`
+        fn mode_changes(&self) -> Rc<std::cell::Cell<usize>> {
+            Rc::clone(&self.mode_changes)
+        }
+    }
+
+    impl stream::Stream for MockStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<(), Error> {
+            self.mode_changes.set(self.mode_changes.get() + 1);
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<(), Error> {
+            self.mode_changes.set(self.mode_changes.get() + 1);
+            Ok(())
+        }
+    }
+
+    impl io::Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let size = buf.len().min(self.test_data.len());
+            buf[..size].copy_from_slice(&self.test_data[..size]);
+            self.test_data.drain(..size);
+            Ok(size)
+        }
+    }
+
+    impl io::Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.borrow_mut().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn telnet_from(stream: MockStream, buf_size: usize) -> Telnet {
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+
+        Telnet::from_stream(Box::new(stream), buf_size)
+    }
+
+    #[test]
+    fn escapes_double_iac_correctly() {
+        let stream = MockStream::new(vec![0x40, 0x5a, 0xff, 0xff, 0x31, 0x34]);
+        let mut telnet = telnet_from(stream, 6);
+
+        let expected_bytes_1: [u8; 2] = [0x40, 0x5a];
+        let expected_bytes_2: [u8; 3] = [0xff, 0x31, 0x34];
+
+        let event_1 = telnet.read_nonblocking().unwrap();
+        if let Event::Data(buffer) = event_1 {
+            assert_eq!(buffer.as_ref(), &expected_bytes_1);
+        } else {
+            panic!();
+        }
+
+        let event_2 = telnet.read_nonblocking().unwrap();
+        if let Event::Data(buffer) = event_2 {
+            assert_eq!(buffer.as_ref(), &expected_bytes_2);
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn subnegotiate_writes_complete_frame_at_once() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 6);
+
+        let size = telnet
+            .subnegotiate(TelnetOption::TTYPE, &[0x00, 0xff, 0x41])
+            .unwrap();
+
+        let expected: [u8; 9] = [0xff, 0xfa, 0x18, 0x00, 0xff, 0xff, 0x41, 0xff, 0xf0];
+        assert_eq!(size, expected.len());
+        assert_eq!(*writes.borrow(), vec![expected.to_vec()]);
+    }
+
+    #[test]
+    fn reassembles_utf8_across_reads() {
+        // "A中", split in the middle of "中"
+        let stream = MockStream::new(vec![0x41, 0xe4, 0xb8, 0xad]);
+        let mut telnet = telnet_from(stream, 2);
+        telnet.set_utf8_reassembly(true);
+
+        let event = telnet.read().unwrap();
+        if let Event::Data(buffer) = event {
+            assert_eq!(buffer.as_ref(), b"A");
+        } else {
+            panic!();
+        }
+
+        let event = telnet.read().unwrap();
+        if let Event::Data(buffer) = event {
+            assert_eq!(buffer.as_ref(), "中".as_bytes());
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn flushes_partial_utf8_tail_on_close() {
+        // The connection closes in the middle of "中"
+        let stream = MockStream::new(vec![0x41, 0xe4, 0xb8]);
+        let mut telnet = telnet_from(stream, 3);
+        telnet.set_utf8_reassembly(true);
+
+        let event = telnet.read_nonblocking().unwrap();
+        if let Event::Data(buffer) = event {
+            assert_eq!(buffer.as_ref(), b"A");
+        } else {
+            panic!();
+        }
+
+        let event = telnet.read_nonblocking().unwrap();
+        if let Event::Data(buffer) = event {
+            assert_eq!(buffer.as_ref(), &[0xe4, 0xb8]);
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn preallocates_event_queue() {
+        let stream = MockStream::new(Vec::new());
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream_with_capacity(Box::new(stream), 6, 16);
+
+        let capacity = telnet.event_queue.capacity();
+        assert!(capacity >= 16);
+
+        for _ in 0..16 {
+            telnet.event_queue.push_event(Event::NoData);
+        }
+        assert_eq!(telnet.event_queue.capacity(), capacity);
+    }
+
+    #[test]
+    fn decodes_exopl_subnegotiation() {
+        // IAC SB EXOPL DO 7 IAC SE
+        let stream = MockStream::new(vec![0xff, 0xfa, 0xff, 0xfd, 0x07, 0xff, 0xf0]);
+        let mut telnet = telnet_from(stream, 7);
+
+        let event = telnet.read_nonblocking().unwrap();
+        if let Event::ExtendedOption(ext) = event {
+            assert_eq!(ext, exopl::ExtendedOption::Negotiation(Action::Do, 7));
+        } else {
+            panic!();
+        }
+    }
+
+    // Never has data, and gives up waiting early on the first read
+    struct SilentStream {
+        read_timeout: std::cell::Cell<Option<Duration>>,
+        reads: usize,
+    }
+
+    impl stream::Stream for SilentStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), Error> {
+            self.read_timeout.set(dur);
+            Ok(())
+        }
+    }
+
+    impl io::Read for SilentStream {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            if self.reads > 1 {
+                std::thread::sleep(self.read_timeout.get().unwrap());
+            }
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl io::Write for SilentStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Never takes anything written, and would block once its write timeout expires
+    struct StuckStream {
+        write_timeout: std::cell::Cell<Option<Duration>>,
+    }
+
+    impl stream::Stream for StuckStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn set_write_timeout(&self, dur: Option<Duration>) -> Result<(), Error> {
+            self.write_timeout.set(dur);
+            Ok(())
+        }
+
+        fn write_timeout(&self) -> Result<Option<Duration>, Error> {
+            Ok(self.write_timeout.get())
+        }
+    }
+
+    impl io::Read for StuckStream {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl io::Write for StuckStream {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            std::thread::sleep(self.write_timeout.get().unwrap());
+            Err(ErrorKind::WouldBlock.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Takes `budget` bytes, then would block `stalls` times, then takes everything
+    struct StallingStream {
+        wire: Rc<RefCell<Vec<u8>>>,
+        budget: Option<usize>,
+        stalls: usize,
+    }
+
+    impl stream::Stream for StallingStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl io::Read for StallingStream {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl io::Write for StallingStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let size = match self.budget {
+                Some(0) => {
+                    self.stalls = self.stalls.saturating_sub(1);
+                    if self.stalls == 0 {
+                        self.budget = None;
+                    }
+                    return Err(ErrorKind::WouldBlock.into());
+                }
+                Some(budget) => {
+                    let size = budget.min(buf.len());
+                    self.budget = Some(budget - size);
+                    size
+                }
+                None => buf.len(),
+            };
+            self.wire.borrow_mut().extend_from_slice(&buf[..size]);
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_frames_whole_and_in_order() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = StallingStream {
+            wire: Rc::clone(&wire),
+            budget: Some(5),
+            stalls: 1,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        // IAC WILL NAWS is written, then the stream blocks 2 bytes into the subnegotiation, whose
+        // rest is kept
+        telnet.negotiate(&Action::Will, TelnetOption::NAWS).unwrap();
+        telnet
+            .subnegotiate(TelnetOption::NAWS, &[0, 80, 0, 24])
+            .unwrap();
+        telnet.negotiate(&Action::Do, TelnetOption::Echo).unwrap();
+        telnet.subnegotiate(TelnetOption::TTYPE, &[1]).unwrap();
+
+        assert_eq!(
+            *wire.borrow(),
+            [
+                0xff, 0xfb, 0x1f, // IAC WILL NAWS
+                0xff, 0xfa, 0x1f, 0, 80, 0, 24, 0xff, 0xf0, // IAC SB NAWS 80 24 IAC SE
+                0xff, 0xfd, 0x01, // IAC DO ECHO
+                0xff, 0xfa, 0x18, 0x01, 0xff, 0xf0, // IAC SB TTYPE SEND IAC SE
+            ]
+        );
+    }
+
+    // Takes `budget` bytes, then fails for good
+    struct FailingStream {
+        wire: Rc<RefCell<Vec<u8>>>,
+        budget: usize,
+        error: ErrorKind,
+    }
+
+    impl stream::Stream for FailingStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl io::Read for FailingStream {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl io::Write for FailingStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.budget == 0 {
+                return Err(self.error.into());
+            }
+            let size = self.budget.min(buf.len());
+            self.budget -= size;
+            self.wire.borrow_mut().extend_from_slice(&buf[..size]);
+            Ok(size)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn uncorks_into_blocked_stream() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = StallingStream {
+            wire: Rc::clone(&wire),
+            budget: Some(2),
+            stalls: 3,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        // "cd" is kept, and the stream still blocks when uncorking
+        telnet.write_raw(b"abcd").unwrap();
+        telnet.cork().unwrap();
+        telnet.write_raw(b"ef").unwrap();
+        telnet.uncork().unwrap();
+        assert_eq!(*wire.borrow(), b"ab");
+
+        assert_eq!(telnet.flush().unwrap_err().kind(), ErrorKind::WouldBlock);
+        telnet.flush().unwrap();
+        assert_eq!(*wire.borrow(), b"abcdef");
+    }
+
+    #[test]
+    fn records_negotiation_kept_for_blocked_stream() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = StallingStream {
+            wire: Rc::clone(&wire),
+            budget: Some(1),
+            stalls: 1,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        // The stream blocks after IAC, and the rest is kept
+        telnet.negotiate(&Action::Will, TelnetOption::NAWS).unwrap();
+        assert!(telnet
+            .options
+            .is_redundant(Action::Will, TelnetOption::NAWS));
+
+        // So the same request is not sent again
+        telnet.negotiate(&Action::Will, TelnetOption::NAWS).unwrap();
+        telnet.flush().unwrap();
+        assert_eq!(*wire.borrow(), [0xff, 0xfb, 0x1f]);
+    }
+
+    #[test]
+    fn reports_progress_of_failed_write() {
+        let data = b"ab\xffcd\xffef";
+        // The escaped data is "ab IAC IAC cd IAC IAC ef". With a budget of 3 or 7, the stream
+        // fails between an IAC byte and its escape, which is kept.
+        for (budget, progress, kept) in [
+            (0, None, &[][..]),
+            (2, Some(2), &[]),
+            (3, Some(3), &[0xff]),
+            (4, Some(3), &[]),
+            (7, Some(6), &[0xff]),
+        ] {
+            for max_chunk in [None, Some(3)] {
+                // Written whole, and as two blocks with `write_chunks`
+                for blocks in [None, Some([&data[..3], &data[3..]])] {
+                    let wire = Rc::new(RefCell::new(Vec::new()));
+                    let stream = FailingStream {
+                        wire: Rc::clone(&wire),
+                        budget,
+                        error: ErrorKind::ConnectionReset,
+                    };
+                    #[cfg(feature = "zcstream")]
+                    let stream = ZlibStream::from_stream(stream);
+                    let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+                    telnet.set_max_write_chunk(max_chunk);
+
+                    let result = match blocks {
+                        Some(blocks) => telnet.write_chunks(blocks),
+                        None => telnet.write(data),
+                    };
+                    let case = format!("budget {budget}, chunk {max_chunk:?}, blocks {blocks:?}");
+                    match progress {
+                        Some(progress) => assert_eq!(result.unwrap(), progress, "{case}"),
+                        None => assert_eq!(
+                            result.unwrap_err().kind(),
+                            ErrorKind::ConnectionReset,
+                            "{case}"
+                        ),
+                    }
+                    assert_eq!(wire.borrow().len(), budget, "{case}");
+                    assert_eq!(telnet.outbound, kept, "{case}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sends_kept_bytes_before_reading() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = StallingStream {
+            wire: Rc::clone(&wire),
+            budget: Some(0),
+            stalls: 1,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        telnet.negotiate(&Action::Do, TelnetOption::Echo).unwrap();
+        assert_eq!(telnet.outbound, [0xff, 0xfd, 0x01]);
+
+        // The stream has nothing to read, but the negotiation is sent before waiting for it
+        assert!(telnet.read().is_err());
+        assert!(telnet.outbound.is_empty());
+        assert_eq!(*wire.borrow(), [0xff, 0xfd, 0x01]);
+    }
+
+    #[test]
+    fn returns_expired_write_timeouts() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = FailingStream {
+            wire: Rc::clone(&wire),
+            budget: 2,
+            error: ErrorKind::TimedOut,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        assert_eq!(telnet.write(b"abcd").unwrap(), 2);
+        let err = telnet.write(b"cd").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(telnet.outbound.is_empty());
+        assert_eq!(*wire.borrow(), b"ab");
+    }
+
+    #[test]
+    fn returns_would_block_of_blocking_stream() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = StallingStream {
+            wire: Rc::clone(&wire),
+            budget: Some(2),
+            stalls: 2,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        // The read puts the stream in blocking mode, where it would block on a write timeout
+        assert!(telnet.read().is_err());
+        assert_eq!(telnet.write(b"abcd").unwrap(), 2);
+        let err = telnet.write(b"cd").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        assert!(telnet.outbound.is_empty());
+    }
+
+    #[test]
+    fn stops_chunked_write_chunks_at_failure() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = FailingStream {
+            wire: Rc::clone(&wire),
+            budget: 4,
+            error: ErrorKind::ConnectionReset,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+        telnet.set_max_write_chunk(Some(2));
+
+        // The stream fails in the middle of "def", and "gh" is not written
+        assert_eq!(telnet.write_chunks(["abc", "def", "gh"]).unwrap(), 4);
+        assert_eq!(*wire.borrow(), b"abcd");
+    }
+
+    #[test]
+    fn keeps_corked_bytes_on_failed_uncork() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = FailingStream {
+            wire: Rc::clone(&wire),
+            budget: 2,
+            error: ErrorKind::ConnectionReset,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        telnet.cork().unwrap();
+        telnet.write_raw(b"abcd").unwrap();
+        assert_eq!(
+            telnet.uncork().unwrap_err().kind(),
+            ErrorKind::ConnectionReset
+        );
+        assert_eq!(*wire.borrow(), b"ab");
+        assert_eq!(telnet.cork_buffer.as_deref(), Some(&b"cd"[..]));
+    }
+
+    #[test]
+    fn keeps_rest_of_blocked_write() {
+        let wire = Rc::new(RefCell::new(Vec::new()));
+        let stream = StallingStream {
+            wire: Rc::clone(&wire),
+            budget: Some(3),
+            stalls: 1,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        // The stream blocks in the middle, so the rest is kept and all the data counts as sent
+        assert_eq!(telnet.write(b"ab\xffcd").unwrap(), 5);
+        telnet.flush().unwrap();
+        assert_eq!(*wire.borrow(), b"ab\xff\xffcd");
+    }
+
+    #[test]
+    fn read_timeout_waits_for_full_duration() {
+        let stream = SilentStream {
+            read_timeout: std::cell::Cell::new(None),
+            reads: 0,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 6);
+
+        let timeout = Duration::from_millis(50);
+        let start = Instant::now();
+        let event = telnet.read_timeout(timeout).unwrap();
+
+        assert!(matches!(event, Event::TimedOut));
+        assert!(start.elapsed() >= timeout);
+    }
+
+    // IAC WILL ECHO, IAC WILL NAWS, IAC WILL TTYPE, IAC WILL EOR
+    const FOUR_NEGOTIATIONS: [u8; 12] = [
+        0xff, 0xfb, 0x01, 0xff, 0xfb, 0x1f, 0xff, 0xfb, 0x18, 0xff, 0xfb, 0x19,
+    ];
+
+    #[test]
+    fn queue_overflow_drops_newest() {
+        let stream = MockStream::new(FOUR_NEGOTIATIONS.to_vec());
+        let mut telnet = telnet_from(stream, 12);
+        telnet.set_max_queued_events(2);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::NAWS)
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Error(TelnetError::QueueOverflow)));
+    }
+
+    #[test]
+    fn queue_overflow_drops_oldest() {
+        let stream = MockStream::new(FOUR_NEGOTIATIONS.to_vec());
+        let mut telnet = telnet_from(stream, 12);
+        telnet.set_max_queued_events(2);
+        telnet.set_queue_overflow_policy(QueueOverflowPolicy::DropOldest);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Error(TelnetError::QueueOverflow)));
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::EOR)
+        ));
+    }
+
+    #[test]
+    fn sends_nvt_control_commands() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 6);
+
+        telnet.send_interrupt_process().unwrap();
+        telnet.send_abort_output().unwrap();
+        telnet.send_erase_line().unwrap();
+
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xf4], vec![0xff, 0xf5], vec![0xff, 0xf8]]
+        );
+    }
+
+    #[test]
+    fn sends_single_byte_command() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 6);
+
+        // IAC NOP
+        telnet.send_command(0xf1).unwrap();
+        assert_eq!(*writes.borrow(), vec![vec![0xff, 0xf1]]);
+    }
+
+    #[test]
+    fn decodes_lflow_subnegotiation() {
+        // IAC SB LFLOW RESTART-XON IAC SE
+        let stream = MockStream::new(vec![0xff, 0xfa, 0x21, 0x03, 0xff, 0xf0]);
+        let mut telnet = telnet_from(stream, 6);
+
+        let event = telnet.read_nonblocking().unwrap();
+        assert!(matches!(event, Event::LFlow(lflow::LFlowCmd::RestartXon)));
+    }
+
+    #[test]
+    fn coalesces_data_from_several_reads() {
+        let stream = MockStream::new(b"abcdef".to_vec());
+        let mut telnet = telnet_from(stream, 2);
+        telnet.set_coalesce(Some((4, Duration::from_secs(1))));
+
+        let event = telnet.read().unwrap();
+        if let Event::Data(buffer) = event {
+            assert_eq!(buffer.as_ref(), b"abcd");
+        } else {
+            panic!();
+        }
+
+        // The rest is delivered when the connection closes
+        let event = telnet.read().unwrap();
+        if let Event::Data(buffer) = event {
+            assert_eq!(buffer.as_ref(), b"ef");
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn resumes_command_split_after_iac() {
+        // "A" IAC | WILL ECHO
+        let stream = MockStream::new(vec![0x41, 0xff, 0xfb, 0x01]);
+        let mut telnet = telnet_from(stream, 2);
+
+        let event = telnet.read().unwrap();
+        if let Event::Data(buffer) = event {
+            assert_eq!(buffer.as_ref(), b"A");
+        } else {
+            panic!();
+        }
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+    }
+
+    #[test]
+    fn tracks_pending_negotiations() {
+        // IAC WILL COMPRESS2
+        let stream = MockStream::new(vec![0xff, 0xfb, 0x56]);
+        let mut telnet = telnet_from(stream, 3);
+
+        telnet
+            .negotiate(&Action::Do, TelnetOption::Compress2)
+            .unwrap();
+        assert_eq!(
+            telnet.pending_negotiations(),
+            vec![(Action::Do, TelnetOption::Compress2)]
+        );
+
+        telnet.read().unwrap();
+        assert!(telnet.pending_negotiations().is_empty());
+    }
+
+    #[test]
+    fn reports_raw_unknown_commands() {
+        // IAC 0x99 "ab" IAC WILL ECHO "c"
+        let stream = MockStream::new(vec![0xff, 0x99, 0x61, 0x62, 0xff, 0xfb, 0x01, 0x63]);
+        let mut telnet = telnet_from(stream, 8);
+        telnet.set_raw_commands(true);
+
+        let event = telnet.read().unwrap();
+        if let Event::RawCommand(command) = event {
+            assert_eq!(command.as_ref(), &[0x99, 0x61, 0x62]);
+        } else {
+            panic!();
+        }
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+
+        let event = telnet.read().unwrap();
+        if let Event::Data(buffer) = event {
+            assert_eq!(buffer.as_ref(), b"c");
+        } else {
             panic!();
         }
     }
+
+    // Returns one chunk per read, then waits for the whole read timeout
+    struct DribbleStream {
+        chunks: VecDeque<Vec<u8>>,
+        read_timeout: std::cell::Cell<Option<Duration>>,
+    }
+
+    impl DribbleStream {
+        fn new(chunks: &[&[u8]]) -> DribbleStream {
+            DribbleStream {
+                chunks: chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+                read_timeout: std::cell::Cell::new(None),
+            }
+        }
+    }
+
+    impl stream::Stream for DribbleStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, dur: Option<Duration>) -> Result<(), Error> {
+            self.read_timeout.set(dur);
+            Ok(())
+        }
+    }
+
+    impl io::Read for DribbleStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if let Some(chunk) = self.chunks.pop_front() {
+                buf[..chunk.len()].copy_from_slice(&chunk);
+                Ok(chunk.len())
+            } else {
+                std::thread::sleep(self.read_timeout.get().unwrap());
+                Err(ErrorKind::WouldBlock.into())
+            }
+        }
+    }
+
+    impl io::Write for DribbleStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn telnet_from_dribble(stream: DribbleStream, buf_size: usize) -> Telnet {
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+
+        Telnet::from_stream(Box::new(stream), buf_size)
+    }
+
+    #[test]
+    fn collects_events_until_deadline() {
+        let stream = DribbleStream::new(&[b"ab", b"cd"]);
+        let mut telnet = telnet_from_dribble(stream, 8);
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(50);
+        let events: Vec<Event> = telnet.events_until(deadline).map(Result::unwrap).collect();
+
+        assert!(Instant::now() >= deadline);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], Event::Data(buffer) if buffer.as_ref() == b"ab"));
+        assert!(matches!(&events[1], Event::Data(buffer) if buffer.as_ref() == b"cd"));
+        assert!(matches!(events[2], Event::TimedOut));
+    }
+
+    #[test]
+    fn sends_window_size_only_when_changed() {
+        // IAC DO NAWS
+        let stream = MockStream::new(vec![0xff, 0xfd, 0x1f]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 3);
+
+        // Not agreed yet
+        assert!(!telnet.update_window_size(80, 24).unwrap());
+
+        telnet.read().unwrap();
+        telnet.negotiate(&Action::Will, TelnetOption::NAWS).unwrap();
+        writes.borrow_mut().clear();
+
+        assert!(telnet.update_window_size(80, 24).unwrap());
+        assert!(!telnet.update_window_size(80, 24).unwrap());
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xfa, 0x1f, 0x00, 0x50, 0x00, 0x18, 0xff, 0xf0]]
+        );
+    }
+
+    #[test]
+    fn sends_window_size_again_after_naws_is_reenabled() {
+        // IAC DO NAWS, IAC DONT NAWS, IAC DO NAWS
+        let stream = MockStream::new(vec![0xff, 0xfd, 0x1f, 0xff, 0xfe, 0x1f, 0xff, 0xfd, 0x1f]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 3);
+
+        telnet.read().unwrap();
+        telnet.negotiate(&Action::Will, TelnetOption::NAWS).unwrap();
+        assert!(telnet.update_window_size(80, 24).unwrap());
+
+        telnet.read().unwrap();
+        assert!(!telnet.update_window_size(80, 24).unwrap());
+
+        telnet.read().unwrap();
+        telnet.negotiate(&Action::Will, TelnetOption::NAWS).unwrap();
+        writes.borrow_mut().clear();
+        assert!(telnet.update_window_size(80, 24).unwrap());
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xfa, 0x1f, 0x00, 0x50, 0x00, 0x18, 0xff, 0xf0]]
+        );
+    }
+
+    #[test]
+    fn subnegotiates_from_reader() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 6);
+
+        let payload = std::io::Cursor::new(vec![0x41, 0xff, 0x42, 0xff]);
+        let size = telnet
+            .subnegotiate_from_reader(TelnetOption::GMCP, payload)
+            .unwrap();
+
+        let wire: Vec<u8> = writes.borrow().concat();
+        assert_eq!(
+            wire,
+            vec![0xff, 0xfa, 0xc9, 0x41, 0xff, 0xff, 0x42, 0xff, 0xff, 0xff, 0xf0]
+        );
+        assert_eq!(size, wire.len());
+    }
+
+    #[cfg(feature = "zcstream")]
+    #[test]
+    fn reports_zlib_state() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 6);
+        assert!(!telnet.is_zlib_active());
+
+        telnet.begin_zlib();
+        assert!(telnet.is_zlib_active());
+
+        telnet.end_zlib();
+        assert!(!telnet.is_zlib_active());
+
+        let result = telnet.begin_zlib_with_dictionary(b"dictionary");
+        #[cfg(feature = "zlib-dictionary")]
+        {
+            result.unwrap();
+            assert!(telnet.is_zlib_active());
+        }
+        #[cfg(not(feature = "zlib-dictionary"))]
+        {
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+            assert!(!telnet.is_zlib_active());
+        }
+    }
+
+    #[test]
+    fn sends_mssp_variables() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 6);
+
+        telnet
+            .send_mssp(&[
+                ("NAME", &["Mud"]),
+                ("PLAYERS", &["42"]),
+                ("PORT", &["23", "4000"]),
+            ])
+            .unwrap();
+
+        let mut expected = vec![0xff, 0xfa, 0x46];
+        expected.extend_from_slice(b"\x01NAME\x02Mud\x01PLAYERS\x0242\x01PORT\x0223\x024000");
+        expected.extend_from_slice(&[0xff, 0xf0]);
+        assert_eq!(*writes.borrow(), vec![expected]);
+    }
+
+    #[test]
+    fn skips_repeated_negotiation() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 6);
+
+        telnet
+            .negotiate(&Action::Do, TelnetOption::Compress2)
+            .unwrap();
+        telnet
+            .negotiate(&Action::Do, TelnetOption::Compress2)
+            .unwrap();
+
+        assert_eq!(*writes.borrow(), vec![vec![0xff, 0xfd, 0x56]]);
+    }
+
+    // IAC SB TTYPE SEND IAC SE
+    const TTYPE_SEND: [u8; 6] = [0xff, 0xfa, 0x18, 0x01, 0xff, 0xf0];
+
+    #[test]
+    fn allows_unsolicited_subnegotiation_by_default() {
+        let mut telnet = telnet_from(MockStream::new(TTYPE_SEND.to_vec()), 6);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Subnegotiation(TelnetOption::TTYPE, _)
+        ));
+    }
+
+    #[test]
+    fn reports_disallowed_unsolicited_subnegotiation() {
+        let mut telnet = telnet_from(MockStream::new(TTYPE_SEND.to_vec()), 6);
+        telnet.set_allow_unsolicited_subnegotiation(false);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Error(TelnetError::UnsolicitedSubnegotiation(TelnetOption::TTYPE))
+        ));
+    }
+
+    #[test]
+    fn reports_buffer_capacity_and_queued_events() {
+        // IAC WILL ECHO IAC WILL NAWS
+        let stream = MockStream::new(vec![0xff, 0xfb, 0x01, 0xff, 0xfb, 0x1f]);
+        let mut telnet = telnet_from(stream, 64);
+        assert_eq!(telnet.buffer_capacity(), 64);
+        assert_eq!(telnet.queued_event_count(), 0);
+
+        telnet.read().unwrap();
+        assert_eq!(telnet.queued_event_count(), 1);
+    }
+
+    #[test]
+    fn answers_configured_terminal_type() {
+        // IAC DO TTYPE, IAC SB TTYPE SEND IAC SE
+        let mut data = vec![0xff, 0xfd, 0x18];
+        data.extend_from_slice(&TTYPE_SEND);
+        let stream = MockStream::new(data);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+        telnet.set_terminal_type("xterm");
+        telnet
+            .negotiate(&Action::Will, TelnetOption::TTYPE)
+            .unwrap();
+        writes.borrow_mut().clear();
+
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Negotiation(Action::Do, TelnetOption::TTYPE)
+        ));
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::OptionEnabled(TelnetOption::TTYPE)
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Subnegotiation(TelnetOption::TTYPE, _)
+        ));
+        // IAC SB TTYPE IS "xterm" IAC SE
+        assert_eq!(
+            *writes.borrow(),
+            vec![b"\xff\xfa\x18\x00xterm\xff\xf0".to_vec()]
+        );
+    }
+
+    #[test]
+    fn answers_configured_terminal_speed() {
+        // IAC DO TSPEED, IAC SB TSPEED SEND IAC SE
+        let stream = MockStream::new(vec![0xff, 0xfd, 0x20, 0xff, 0xfa, 0x20, 0x01, 0xff, 0xf0]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+        telnet.set_terminal_speed(38400, 9600);
+        telnet
+            .negotiate(&Action::Will, TelnetOption::TSPEED)
+            .unwrap();
+        writes.borrow_mut().clear();
+
+        telnet.read().unwrap();
+        assert_eq!(
+            *writes.borrow(),
+            vec![b"\xff\xfa\x20\x0038400,9600\xff\xf0".to_vec()]
+        );
+    }
+
+    #[test]
+    fn answers_configured_environment() {
+        // IAC DO NEW-ENVIRON, IAC SB NEW-ENVIRON SEND IAC SE
+        let stream = MockStream::new(vec![0xff, 0xfd, 0x27, 0xff, 0xfa, 0x27, 0x01, 0xff, 0xf0]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+        telnet.set_environment(&[("USER", "me")]);
+        telnet
+            .negotiate(&Action::Will, TelnetOption::NewEnvironment)
+            .unwrap();
+        writes.borrow_mut().clear();
+
+        telnet.read().unwrap();
+        assert_eq!(
+            *writes.borrow(),
+            vec![b"\xff\xfa\x27\x00\x00USER\x01me\xff\xf0".to_vec()]
+        );
+    }
+
+    #[test]
+    fn answers_do_naws_with_configured_window_size() {
+        // IAC DO NAWS
+        let stream = MockStream::new(vec![0xff, 0xfd, 0x1f]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+        telnet.set_window_size(80, 24).unwrap();
+        assert!(writes.borrow().is_empty());
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Do, TelnetOption::NAWS)
+        ));
+        // IAC WILL NAWS, IAC SB NAWS 0 80 0 24 IAC SE
+        assert_eq!(
+            *writes.borrow(),
+            vec![
+                vec![0xff, 0xfb, 0x1f],
+                vec![0xff, 0xfa, 0x1f, 0, 80, 0, 24, 0xff, 0xf0]
+            ]
+        );
+    }
+
+    #[test]
+    fn answers_unsolicited_sends_only_when_enabled() {
+        // IAC SB NEW-ENVIRON SEND IAC SE, twice
+        let send = [0xff, 0xfa, 0x27, 0x01, 0xff, 0xf0];
+        let stream = MockStream::new([send, send].concat());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 6);
+        telnet.set_environment(&[("USER", "me")]);
+
+        // NEW-ENVIRON was never negotiated
+        telnet.read().unwrap();
+        assert!(writes.borrow().is_empty());
+
+        let mut policy = telnet.subnegotiation_policy.clone();
+        policy.answer_unsolicited = true;
+        telnet.set_subnegotiation_policy(policy);
+        telnet.read().unwrap();
+        assert_eq!(
+            *writes.borrow(),
+            vec![b"\xff\xfa\x27\x00\x00USER\x01me\xff\xf0".to_vec()]
+        );
+    }
+
+    #[test]
+    fn does_not_answer_unconfigured_sends() {
+        // IAC DO NAWS, IAC SB TTYPE SEND IAC SE
+        let mut data = vec![0xff, 0xfd, 0x1f];
+        data.extend_from_slice(&TTYPE_SEND);
+        let stream = MockStream::new(data);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+        telnet.set_terminal_speed(9600, 9600);
+
+        telnet.read().unwrap();
+        telnet.read().unwrap();
+        assert!(writes.borrow().is_empty());
+    }
+
+    #[test]
+    fn read_returns_idle_after_idle_timeout() {
+        let stream = SilentStream {
+            read_timeout: std::cell::Cell::new(None),
+            reads: 0,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 6);
+        telnet.set_idle_timeout(Some(Duration::from_millis(50)));
+
+        let start = Instant::now();
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Idle));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn reports_subnegotiation_cut_off_by_close() {
+        // IAC SB TTYPE IS "vt", then the connection closes
+        let stream = MockStream::new(vec![0xff, 0xfa, 0x18, 0x00, b'v', b't']);
+        let mut telnet = telnet_from(stream, 16);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Error(TelnetError::IncompleteSubnegotiation(TelnetOption::TTYPE, ref data))
+                if data.as_ref() == b"\x00vt"
+        ));
+        assert_eq!(telnet.read().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn feed_queues_events_without_reading() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 4);
+
+        // IAC WILL ECHO, "login: ", IAC DO TTYPE, split by the buffer size
+        telnet.feed(b"\xff\xfb\x01login: \xff\xfd\x18");
+
+        let mut events = Vec::new();
+        loop {
+            match telnet.read_nonblocking() {
+                Ok(Event::NoData) => panic!("fed events should come before reading"),
+                Ok(event) => events.push(event),
+                Err(e) => {
+                    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+                    break;
+                }
+            }
+        }
+
+        assert!(matches!(
+            events[0],
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        let data: Vec<u8> = events[1..events.len() - 1]
+            .iter()
+            .flat_map(|event| match event {
+                Event::Data(data) => data.to_vec(),
+                _ => panic!("unexpected event: {:?}", event),
+            })
+            .collect();
+        assert_eq!(data, b"login: ");
+        assert!(matches!(
+            events[events.len() - 1],
+            Event::Negotiation(Action::Do, TelnetOption::TTYPE)
+        ));
+    }
+
+    #[test]
+    fn sends_authentication_reject() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        telnet.send_auth_reply(&auth::AuthReply::Reject).unwrap();
+        // IAC SB AUTHENTICATION IS NULL 0 IAC SE
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xfa, 0x25, 0x00, 0x00, 0x00, 0xff, 0xf0]]
+        );
+    }
+
+    #[test]
+    fn buffered_stream_collects_small_writes() {
+        let negotiate_three = |telnet: &mut Telnet| {
+            telnet.negotiate(&Action::Will, TelnetOption::Echo).unwrap();
+            telnet.negotiate(&Action::Do, TelnetOption::NAWS).unwrap();
+            telnet.negotiate(&Action::Do, TelnetOption::TTYPE).unwrap();
+            telnet.flush().unwrap();
+        };
+
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        negotiate_three(&mut telnet_from(stream, 16));
+        assert_eq!(writes.borrow().len(), 3);
+
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let telnet = telnet_from(stream, 16);
+        let mut telnet = Telnet::from_stream_buffered(telnet.stream, 16, 64);
+        negotiate_three(&mut telnet);
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xfb, 0x01, 0xff, 0xfd, 0x1f, 0xff, 0xfd, 0x18]]
+        );
+    }
+
+    #[test]
+    fn reports_raw_frames_when_enabled() {
+        // IAC WILL ECHO, IAC SB TTYPE IS 0xff IAC SE
+        let frames: [&[u8]; 2] = [
+            &[0xff, 0xfb, 0x01],
+            &[0xff, 0xfa, 0x18, 0x00, 0xff, 0xff, 0xff, 0xf0],
+        ];
+        let mut telnet = telnet_from(MockStream::new(frames.concat()), 16);
+        telnet.set_raw_frames(true);
+
+        for frame in frames {
+            let event = telnet.read().unwrap();
+            assert!(matches!(event, Event::RawFrame(ref raw) if raw.as_ref() == frame));
+            let event = telnet.read().unwrap();
+            assert!(!matches!(event, Event::RawFrame(_)));
+        }
+    }
+
+    // IAC SB TTYPE IS "vt" IAC 'x' "100" IAC SE "ok"
+    const GARBLED_TTYPE: &[u8] = b"\xff\xfa\x18\x00vt\xffx100\xff\xf0ok";
+
+    #[test]
+    fn skips_unexpected_byte_in_subnegotiation() {
+        let mut telnet = telnet_from(MockStream::new(GARBLED_TTYPE.to_vec()), 32);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Error(TelnetError::UnexpectedByte(b'x'))
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Subnegotiation(TelnetOption::TTYPE, ref data) if data.as_ref() == b"\x00vt100"
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"ok"));
+    }
+
+    #[test]
+    fn drops_malformed_subnegotiation_in_strict_mode() {
+        let mut telnet = telnet_from(MockStream::new(GARBLED_TTYPE.to_vec()), 32);
+        telnet.set_strict_subnegotiation(true);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Error(TelnetError::MalformedSubnegotiation(TelnetOption::TTYPE, ref data))
+                if data.as_ref() == b"\x00vt"
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"ok"));
+    }
+
+    #[test]
+    fn write_str_escapes_encoded_text() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        // U+00FF is 0xc3 0xbf in UTF-8, with no IAC to escape
+        telnet.write_str("\u{ff}").unwrap();
+        assert_eq!(writes.borrow().concat(), [0xc3, 0xbf]);
+
+        // but it is IAC itself in Latin-1
+        writes.borrow_mut().clear();
+        telnet.set_encoding(Box::new(encoding::Latin1));
+        telnet.write_str("\u{ff}").unwrap();
+        assert_eq!(writes.borrow().concat(), [0xff, 0xff]);
+    }
+
+    #[test]
+    fn uses_separate_input_and_output_encodings() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+        telnet.set_input_encoding(Box::new(encoding::Latin1));
+        telnet.set_output_encoding(Box::new(encoding::Utf8));
+
+        assert_eq!(telnet.decode_input(&[0xff]), "\u{ff}");
+        telnet.write_str("\u{ff}").unwrap();
+        assert_eq!(writes.borrow().concat(), [0xc3, 0xbf]);
+    }
+
+    #[test]
+    fn decodes_utf8_input_across_reads() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 16);
+        assert_eq!(telnet.decode_input(&[b'a', 0xc3]), "a");
+        assert_eq!(telnet.decode_input(&[0xbf]), "\u{ff}");
+    }
+
+    #[test]
+    fn deadline_is_shared_by_reads_and_writes() {
+        let stream = SilentStream {
+            read_timeout: std::cell::Cell::new(None),
+            reads: 0,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 6);
+
+        let start = Instant::now();
+        let mut exchange = telnet.with_deadline(start + Duration::from_millis(50));
+        exchange.write(b"who").unwrap();
+
+        let event = exchange.read().unwrap();
+        assert!(matches!(event, Event::TimedOut));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Anything after the deadline fails right away
+        let event = exchange.read().unwrap();
+        assert!(matches!(event, Event::TimedOut));
+        let err = exchange.write(b"who").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn deadline_expires_during_write() {
+        let stream = StuckStream {
+            write_timeout: std::cell::Cell::new(None),
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+
+        let start = Instant::now();
+        let mut exchange = telnet.with_deadline(start + Duration::from_millis(50));
+        let err = exchange.write(b"who").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        // Nothing is kept to be sent later
+        assert!(telnet.outbound.is_empty());
+    }
+
+    #[test]
+    fn decodes_tn3270_regime_subnegotiation() {
+        // IAC SB TN3270-REGIME IS "3270" IAC SE
+        let stream = MockStream::new(b"\xff\xfa\x1d\x003270\xff\xf0".to_vec());
+        let mut telnet = telnet_from(stream, 16);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Tn3270Regime(ref cmd) if cmd.is_3270()));
+    }
+
+    #[test]
+    fn decodes_encryption_support_and_refuses_it() {
+        // IAC DO ENCRYPT, IAC WILL ENCRYPT, IAC SB ENCRYPT SUPPORT DES_CFB64 DES3_CFB64 IAC SE
+        let stream =
+            MockStream::new(b"\xff\xfd\x26\xff\xfb\x26\xff\xfa\x26\x01\x01\x03\xff\xf0".to_vec());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 32);
+
+        telnet.read().unwrap();
+        telnet.read().unwrap();
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Encryption(encryption::EncryptCmd::Support(ref types))
+                if types.as_ref() == [encryption::TYPE_DES_CFB64, encryption::TYPE_DES3_CFB64]
+        ));
+
+        telnet.refuse_encryption().unwrap();
+        // IAC WONT ENCRYPT, IAC DONT ENCRYPT
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xfc, 0x26], vec![0xff, 0xfe, 0x26]]
+        );
+    }
+
+    #[test]
+    fn keeps_bare_se_in_subnegotiation_by_default() {
+        // IAC SB 0x99 "abc" SE "def" IAC SE
+        let stream = MockStream::new(b"\xff\xfa\x99abc\xf0def\xff\xf0".to_vec());
+        let mut telnet = telnet_from(stream, 32);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Subnegotiation(TelnetOption::UnknownOption(0x99), ref data)
+                if data.as_ref() == b"abc\xf0def"
+        ));
+    }
+
+    #[test]
+    fn ends_subnegotiation_on_bare_se_when_lenient() {
+        // IAC SB 0x99 "a" SE "bc" SE "de"
+        let stream = MockStream::new(b"\xff\xfa\x99a\xf0bc\xf0de".to_vec());
+        let mut telnet = telnet_from(stream, 32);
+        telnet.set_lenient_subnegotiation_end(Some(3));
+
+        // The first SE comes too early to end it
+        let opt = TelnetOption::UnknownOption(0x99);
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Error(TelnetError::BareSubnegotiationEnd(o)) if o == opt
+        ));
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Subnegotiation(o, ref data) if o == opt && data.as_ref() == b"a\xf0bc"
+        ));
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Data(ref data) if data.as_ref() == b"de"
+        ));
+    }
+
+    #[test]
+    fn decodes_rcte_subnegotiation() {
+        // IAC SB RCTE <print text, new break classes> 0x00 0x3f IAC SE
+        let stream = MockStream::new(b"\xff\xfa\x07\x06\x00\x3f\xff\xf0".to_vec());
+        let mut telnet = telnet_from(stream, 16);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Rcte(rcte::RcteCmd {
+                print_break: false,
+                print_text: true,
+                break_classes: Some(0x3f),
+                transmit_classes: None,
+            })
+        ));
+    }
+
+    #[test]
+    fn pipes_data_and_keeps_other_events() {
+        // "ab", IAC WILL ECHO, "cd"
+        let stream = MockStream::new(b"ab\xff\xfb\x01cd".to_vec());
+        let mut telnet = telnet_from(stream, 4);
+
+        // The connection closing ends the piping early
+        let mut out = Vec::new();
+        let size = telnet
+            .pipe_data_to(&mut out, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(size, 4);
+        assert_eq!(out, b"abcd");
+
+        let event = telnet.read_nonblocking().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        let err = telnet.read_nonblocking().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(feature = "zcstream")]
+    #[test]
+    fn handles_mccp2_handshake_automatically() {
+        use flate2::{Compress, Compression, FlushCompress};
+
+        let mut compress = Compress::new(Compression::default(), true);
+        let mut compressed = Vec::with_capacity(128);
+        compress
+            .compress_vec(b"hello", &mut compressed, FlushCompress::Finish)
+            .unwrap();
+
+        // IAC WILL COMPRESS2, IAC SB COMPRESS2 IAC SE, then compressed data in the same read
+        let mut data = vec![0xff, 0xfb, 0x56, 0xff, 0xfa, 0x56, 0xff, 0xf0];
+        data.extend_from_slice(&compressed);
+        let stream = MockStream::new(data);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 64);
+        telnet.set_auto_mccp(true);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::Compress2)
+        ));
+        // IAC DO COMPRESS2
+        assert_eq!(*writes.borrow(), vec![vec![0xff, 0xfd, 0x56]]);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Subnegotiation(TelnetOption::Compress2, _)
+        ));
+        assert!(telnet.is_zlib_active());
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"hello"));
+    }
+
+    #[cfg(feature = "zcstream")]
+    #[test]
+    fn starts_mccp2_after_bare_se_when_lenient() {
+        use flate2::{Compress, Compression, FlushCompress};
+
+        let mut compress = Compress::new(Compression::default(), true);
+        let mut compressed = Vec::with_capacity(128);
+        compress
+            .compress_vec(b"hello", &mut compressed, FlushCompress::Finish)
+            .unwrap();
+
+        // IAC WILL COMPRESS2, IAC SB COMPRESS2 SE, then compressed data in the same read
+        let mut data = vec![0xff, 0xfb, 0x56, 0xff, 0xfa, 0x56, 0xf0];
+        data.extend_from_slice(&compressed);
+        let mut telnet = telnet_from(MockStream::new(data), 64);
+        telnet.set_auto_mccp(true);
+        telnet.set_lenient_subnegotiation_end(Some(0));
+
+        telnet.read().unwrap();
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Error(TelnetError::BareSubnegotiationEnd(TelnetOption::Compress2))
+        ));
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Subnegotiation(TelnetOption::Compress2, _)
+        ));
+        assert!(telnet.is_zlib_active());
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"hello"));
+    }
+
+    #[test]
+    fn auto_responds_and_still_returns_negotiations() {
+        // IAC DO ECHO, IAC WILL SGA
+        let stream = MockStream::new(vec![0xff, 0xfd, 0x01, 0xff, 0xfb, 0x03]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 64);
+        telnet.set_auto_respond(Some(&[TelnetOption::SuppressGoAhead]));
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Do, TelnetOption::Echo)
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::SuppressGoAhead)
+        ));
+        // IAC WONT ECHO, IAC DO SGA
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xfc, 0x01], vec![0xff, 0xfd, 0x03]]
+        );
+        assert!(telnet
+            .options
+            .is_enabled_remotely(TelnetOption::SuppressGoAhead));
+    }
+
+    #[test]
+    fn reads_lines_around_negotiation() {
+        use std::io::BufRead;
+
+        // "hello\nwor" IAC WILL ECHO "ld\nrest"
+        let data = b"hello\nwor\xff\xfb\x01ld\nrest".to_vec();
+        let mut telnet = telnet_from(MockStream::new(data), 64);
+
+        {
+            let mut reader = telnet.data_reader();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "hello\n");
+            assert!(reader.take_event().is_none());
+
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(line, "world\n");
+            assert!(matches!(
+                reader.take_event(),
+                Some(Event::Negotiation(Action::Will, TelnetOption::Echo))
+            ));
+        }
+
+        // The data left in the reader is not lost
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"rest"));
+    }
+
+    #[test]
+    fn data_reader_requeues_into_full_queue() {
+        use std::io::BufRead;
+
+        // Two reads of "ab" IAC WILL ECHO IAC WILL SGA and "cd" IAC WILL NAWS IAC WILL TTYPE
+        let data = b"ab\xff\xfb\x01\xff\xfb\x03cd\xff\xfb\x1f\xff\xfb\x18".to_vec();
+        let mut telnet = telnet_from(MockStream::new(data), 8);
+        telnet.set_max_queued_events(3);
+
+        {
+            let mut reader = telnet.data_reader();
+            assert_eq!(reader.fill_buf().unwrap(), b"ab");
+            reader.consume(2);
+            // Sets aside ECHO and SGA, and leaves NAWS and TTYPE queued
+            assert_eq!(reader.fill_buf().unwrap(), b"cd");
+            reader.consume(1);
+        }
+
+        // Five events are put back in a queue of three, and none is dropped
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Negotiation(Action::Will, TelnetOption::SuppressGoAhead)
+        ));
+        assert!(matches!(telnet.read().unwrap(), Event::Data(ref data) if data.as_ref() == b"d"));
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Negotiation(Action::Will, TelnetOption::NAWS)
+        ));
+        assert!(matches!(
+            telnet.read().unwrap(),
+            Event::Negotiation(Action::Will, TelnetOption::TTYPE)
+        ));
+    }
+
+    #[test]
+    fn strict_negotiation_rejects_unknown_options() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+        telnet.set_strict_negotiation(true);
+
+        let opt = TelnetOption::UnknownOption(0x99);
+        assert!(matches!(
+            telnet.negotiate(&Action::Will, opt),
+            Err(TelnetError::InvalidNegotiation(Action::Will, o)) if o == opt
+        ));
+        assert!(writes.borrow().is_empty());
+
+        // Experimental options are still allowed through negotiate_raw
+        telnet.negotiate_raw(&Action::Will, 0x99).unwrap();
+        telnet.negotiate(&Action::Wont, opt).unwrap();
+        // IAC WILL 0x99, IAC WONT 0x99
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xfb, 0x99], vec![0xff, 0xfc, 0x99]]
+        );
+    }
+
+    #[test]
+    fn tracks_last_activity() {
+        let mut telnet = telnet_from(MockStream::new(b"data".to_vec()), 16);
+        let opened = telnet.last_activity();
+
+        std::thread::sleep(Duration::from_millis(5));
+        telnet.read().unwrap();
+        let read = telnet.last_activity();
+        assert!(read > opened);
+
+        std::thread::sleep(Duration::from_millis(5));
+        telnet.write(b"reply").unwrap();
+        let written = telnet.last_activity();
+        assert!(written > read);
+
+        std::thread::sleep(Duration::from_millis(5));
+        telnet
+            .subnegotiate(TelnetOption::NAWS, &[0, 80, 0, 24])
+            .unwrap();
+        let subnegotiated = telnet.last_activity();
+        assert!(subnegotiated > written);
+
+        // The refusal of IAC DO ECHO is written as well
+        std::thread::sleep(Duration::from_millis(5));
+        telnet.set_auto_respond(Some(&[]));
+        telnet.feed(&[0xff, 0xfd, 0x01]);
+        assert!(telnet.last_activity() > subnegotiated);
+    }
+
+    #[test]
+    fn checked_subnegotiation_requires_agreed_option() {
+        // IAC DO TTYPE
+        let stream = MockStream::new(vec![0xff, 0xfd, 0x18]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        let result = telnet.subnegotiate_checked(TelnetOption::TTYPE, b"\x00xterm");
+        assert!(matches!(
+            result,
+            Err(TelnetError::OptionNotEnabled(TelnetOption::TTYPE))
+        ));
+        assert!(writes.borrow().is_empty());
+
+        telnet.read().unwrap();
+        telnet
+            .negotiate(&Action::Will, TelnetOption::TTYPE)
+            .unwrap();
+        telnet
+            .subnegotiate_checked(TelnetOption::TTYPE, b"\x00xterm")
+            .unwrap();
+        assert_eq!(writes.borrow().len(), 2);
+    }
+
+    #[test]
+    fn delivers_iac_as_data_in_supdup_mode() {
+        let mut telnet = telnet_from(MockStream::new(vec![b'a', 0xff, 0xfb, 0x01]), 16);
+        telnet.enter_supdup_mode();
+
+        let event = telnet.read().unwrap();
+        assert!(
+            matches!(event, Event::Data(ref data) if data.as_ref() == [b'a', 0xff, 0xfb, 0x01])
+        );
+    }
+
+    #[test]
+    fn enters_supdup_mode_once_agreed() {
+        // IAC WILL SUPDUP, answered with IAC DO SUPDUP, then SUPDUP data in the same read
+        let mut telnet = telnet_from(MockStream::new(vec![0xff, 0xfb, 0x15, 0xff, 0x00]), 16);
+        telnet.negotiate(&Action::Do, TelnetOption::SUPDUP).unwrap();
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::SUPDUP)
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::OptionEnabled(TelnetOption::SUPDUP)));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == [0xff, 0x00]));
+    }
+
+    #[test]
+    fn merges_data_around_negotiation_in_transparent_mode() {
+        // "ab", IAC WILL ECHO, "cd", IAC SB TTYPE SEND IAC SE, "ef"
+        let mut data = b"ab\xff\xfb\x01cd".to_vec();
+        data.extend_from_slice(&TTYPE_SEND);
+        data.extend_from_slice(b"ef");
+        let mut telnet = telnet_from(MockStream::new(data), 64);
+        telnet.set_negotiation_transparent(true);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"abcd"));
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Subnegotiation(TelnetOption::TTYPE, _)
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"ef"));
+    }
+
+    #[test]
+    fn negotiates_option_by_raw_byte() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        telnet.negotiate_raw(&Action::Do, 99).unwrap();
+        assert_eq!(*writes.borrow(), vec![vec![0xff, 0xfd, 99]]);
+        assert_eq!(
+            telnet.pending_negotiations(),
+            vec![(Action::Do, TelnetOption::UnknownOption(99))]
+        );
+    }
+
+    #[test]
+    fn read_text_returns_only_data_and_refuses_options() {
+        // IAC WILL ECHO, "hello", IAC DO TTYPE, " world"
+        let stream = MockStream::new(b"\xff\xfb\x01hello\xff\xfd\x18 world".to_vec());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 8);
+
+        let text = telnet.read_text(Duration::from_secs(5)).unwrap();
+        assert_eq!(text, b"hello world");
+        // IAC DONT ECHO, IAC WONT TTYPE
+        assert_eq!(
+            *writes.borrow(),
+            vec![vec![0xff, 0xfe, 0x01], vec![0xff, 0xfc, 0x18]]
+        );
+    }
+
+    #[test]
+    fn processes_empty_buffer_without_events() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 16);
+        telnet.set_raw_commands(true);
+
+        // In the middle of an unknown command, which an empty read must not end
+        telnet.state = ProcessState::RawCommand;
+        telnet.buffered_size = 0;
+        telnet.process();
+        telnet.feed(&[]);
+        assert_eq!(telnet.queued_event_count(), 0);
+        assert!(matches!(telnet.state, ProcessState::RawCommand));
+    }
+
+    #[test]
+    fn defers_subnegotiation_until_option_is_agreed() {
+        // IAC DO NAWS
+        let stream = MockStream::new(vec![0xff, 0xfd, 0x1f]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        let sent = telnet
+            .enable_and_subnegotiate(TelnetOption::NAWS, &[0, 80, 0, 24])
+            .unwrap();
+        assert!(!sent);
+        // IAC WILL NAWS
+        assert_eq!(*writes.borrow(), vec![vec![0xff, 0xfb, 0x1f]]);
+
+        telnet.read().unwrap();
+        assert_eq!(
+            writes.borrow()[1],
+            vec![0xff, 0xfa, 0x1f, 0, 80, 0, 24, 0xff, 0xf0]
+        );
+    }
+
+    #[test]
+    fn drops_deferred_subnegotiation_when_refused() {
+        // IAC DONT NAWS
+        let stream = MockStream::new(vec![0xff, 0xfe, 0x1f]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        telnet
+            .enable_and_subnegotiate(TelnetOption::NAWS, &[0, 80, 0, 24])
+            .unwrap();
+        telnet.read().unwrap();
+        assert_eq!(writes.borrow().len(), 1);
+        assert!(telnet.deferred_subnegotiations.is_empty());
+    }
+
+    #[test]
+    fn returns_processing_errors_as_io_errors() {
+        let mut telnet = telnet_from(MockStream::new(GARBLED_TTYPE.to_vec()), 32);
+        telnet.set_errors_as_io_error(true);
+
+        let err = telnet.read().unwrap_err();
+        assert!(matches!(
+            err.get_ref()
+                .and_then(|inner| inner.downcast_ref::<TelnetError>()),
+            Some(TelnetError::UnexpectedByte(b'x'))
+        ));
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Subnegotiation(TelnetOption::TTYPE, _)
+        ));
+    }
+
+    #[test]
+    fn requests_logout() {
+        // IAC WILL LOGOUT
+        let stream = MockStream::new(vec![0xff, 0xfb, 0x12]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        telnet.request_logout().unwrap();
+        // IAC DO LOGOUT
+        assert_eq!(*writes.borrow(), vec![vec![0xff, 0xfd, 0x12]]);
+        assert!(!telnet.is_logout_acknowledged());
+
+        telnet.read().unwrap();
+        assert!(telnet.is_logout_acknowledged());
+    }
+
+    // Feeds each chunk as a separate read, and returns the data of the queued events
+    fn feed_data(telnet: &mut Telnet, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for chunk in chunks {
+            telnet.feed(chunk);
+            while let Some(Event::Data(buffer)) = telnet.event_queue.take_event() {
+                data.extend_from_slice(&buffer);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn passes_bare_cr_through_by_default() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 16);
+        let data = feed_data(&mut telnet, &[b"a\rb\r\n", b"c\r"]);
+        assert_eq!(data, b"a\rb\r\nc\r");
+    }
+
+    #[test]
+    fn ignores_bare_cr() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 16);
+        telnet.set_bare_cr_policy(BareCrPolicy::Ignore);
+        let data = feed_data(&mut telnet, &[b"a\rb\r\nc\r\0d"]);
+        assert_eq!(data, b"ab\r\nc\r\0d");
+    }
+
+    #[test]
+    fn strips_bare_cr_to_lf() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 16);
+        telnet.set_bare_cr_policy(BareCrPolicy::StripToLf);
+        let data = feed_data(&mut telnet, &[b"a\rb\r\n"]);
+        assert_eq!(data, b"a\nb\r\n");
+    }
+
+    #[test]
+    fn holds_back_cr_at_end_of_read() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 16);
+        telnet.set_bare_cr_policy(BareCrPolicy::StripToLf);
+        assert_eq!(feed_data(&mut telnet, &[b"a\r"]), b"a");
+        assert_eq!(feed_data(&mut telnet, &[b"\nb\r"]), b"\r\nb");
+        assert_eq!(feed_data(&mut telnet, &[b"c"]), b"\nc");
+
+        // A CR ending the stream is bare
+        feed_data(&mut telnet, &[b"d\r"]);
+        telnet.flush_pending();
+        assert_eq!(feed_data(&mut telnet, &[b""]), b"\n");
+    }
+
+    #[test]
+    fn releases_held_back_cr_when_no_data_follows() {
+        let stream = StallingStream {
+            wire: Rc::new(RefCell::new(Vec::new())),
+            budget: None,
+            stalls: 0,
+        };
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let mut telnet = Telnet::from_stream(Box::new(stream), 16);
+        telnet.set_bare_cr_policy(BareCrPolicy::StripToLf);
+
+        telnet.feed(b"login:\r");
+        let event = telnet.read_nonblocking().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"login:"));
+        let event = telnet.read_nonblocking().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"\n"));
+        assert!(matches!(telnet.read_nonblocking().unwrap(), Event::NoData));
+
+        telnet.feed(b"password:\r");
+        telnet.read_nonblocking().unwrap();
+        let event = telnet.read_timeout(Duration::from_millis(10)).unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"\n"));
+    }
+
+    #[test]
+    fn writes_in_chunks_without_splitting_iac() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+        telnet.set_max_write_chunk(Some(4));
+
+        // The escaped IAC would straddle the first chunk boundary
+        assert_eq!(telnet.write(b"abc\xffdefgh").unwrap(), 9);
+        assert_eq!(
+            *writes.borrow(),
+            vec![b"abc".to_vec(), b"\xff\xffde".to_vec(), b"fgh".to_vec()]
+        );
+    }
+
+    #[test]
+    fn decodes_terminal_type_subnegotiations() {
+        // IAC SB TTYPE SEND IAC SE, IAC SB TTYPE IS "vt" 0xff "é" IAC SE
+        let mut data = TTYPE_SEND.to_vec();
+        data.extend_from_slice(b"\xff\xfa\x18\x00vt\xff\xff\xc3\xa9\xff\xf0");
+        let mut telnet = telnet_from(MockStream::new(data), 32);
+        telnet.set_decode_terminal_type(true);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::TerminalTypeRequested));
+        // The escaped IAC is not valid UTF-8, and is replaced
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::TerminalType(ref name) if name == "vt\u{fffd}é"));
+    }
+
+    #[test]
+    fn passes_raw_stream_through_once_detected() {
+        // Binary data without IAC in the first bytes, then an incidental 0xFF
+        let mut data: Vec<u8> = (0..=254).cycle().take(AUTO_DETECT_LEN).collect();
+        data.extend_from_slice(&[0xff, 0xfb, 0x01]);
+        let mut telnet = telnet_from(MockStream::new(data.clone()), 100);
+        telnet.set_auto_detect(true);
+
+        let mut received = Vec::new();
+        while let Ok(Event::Data(buffer)) = telnet.read() {
+            received.extend_from_slice(&buffer);
+        }
+        assert!(telnet.is_passthrough());
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn keeps_parsing_telnet_when_detected() {
+        // "login: ", IAC WILL ECHO
+        let mut telnet = telnet_from(MockStream::new(b"login: \xff\xfb\x01".to_vec()), 4);
+        telnet.set_auto_detect(true);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"logi"));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"n: "));
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        assert!(!telnet.is_passthrough());
+    }
+
+    #[test]
+    fn reads_exact_data_across_reads() {
+        // "abc", IAC WILL ECHO, "defgh", split by the buffer size
+        let stream = MockStream::new(b"abc\xff\xfb\x01defgh".to_vec());
+        let mut telnet = telnet_from(stream, 4);
+
+        let data = telnet.read_exact_data(5, Duration::from_secs(1)).unwrap();
+        assert_eq!(data, b"abcde");
+
+        // The negotiation and the extra data are kept, in order
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        let data = telnet.read_exact_data(3, Duration::from_secs(1)).unwrap();
+        assert_eq!(data, b"fgh");
+
+        let err = telnet
+            .read_exact_data(1, Duration::from_secs(1))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn logs_last_negotiations() {
+        // IAC WILL ECHO, IAC SB TTYPE SEND IAC SE
+        let mut data = vec![0xff, 0xfb, 0x01];
+        data.extend_from_slice(&TTYPE_SEND);
+        let mut telnet = telnet_from(MockStream::new(data), 64);
+        telnet.set_negotiation_log_capacity(3);
+
+        telnet.negotiate(&Action::Do, TelnetOption::Echo).unwrap();
+        telnet.read().unwrap();
+        assert_eq!(
+            *telnet.negotiation_log(),
+            [
+                LoggedNegotiation::Negotiation(Direction::Sent, Action::Do, TelnetOption::Echo),
+                LoggedNegotiation::Negotiation(
+                    Direction::Received,
+                    Action::Will,
+                    TelnetOption::Echo
+                ),
+                LoggedNegotiation::Subnegotiation(
+                    Direction::Received,
+                    TelnetOption::TTYPE,
+                    Box::new([1])
+                ),
+            ]
+        );
+
+        // The oldest entry is dropped
+        telnet.subnegotiate(TelnetOption::TTYPE, b"\x00vt").unwrap();
+        let log = telnet.negotiation_log();
+        assert_eq!(log.len(), 3);
+        assert_eq!(
+            log[0],
+            LoggedNegotiation::Negotiation(Direction::Received, Action::Will, TelnetOption::Echo)
+        );
+        assert_eq!(
+            log[2],
+            LoggedNegotiation::Subnegotiation(
+                Direction::Sent,
+                TelnetOption::TTYPE,
+                Box::new([0, b'v', b't'])
+            )
+        );
+    }
+
+    #[test]
+    fn sends_corked_writes_together() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        telnet.cork().unwrap();
+        telnet
+            .negotiate(&Action::Will, TelnetOption::TTYPE)
+            .unwrap();
+        telnet.subnegotiate(TelnetOption::TTYPE, b"\x00vt").unwrap();
+        telnet.write(b"hi").unwrap();
+        assert!(writes.borrow().is_empty());
+
+        telnet.uncork().unwrap();
+        assert_eq!(
+            *writes.borrow(),
+            vec![b"\xff\xfb\x18\xff\xfa\x18\x00vt\xff\xf0hi".to_vec()]
+        );
+    }
+
+    #[test]
+    fn drops_subnegotiation_over_option_limit() {
+        // IAC SB NAWS 0 80 0 24 0 IAC SE "ok", IAC SB GMCP "Core.Ping" IAC SE
+        let mut data = b"\xff\xfa\x1f\x00\x50\x00\x18\x00\xff\xf0ok".to_vec();
+        data.extend_from_slice(b"\xff\xfa\xc9Core.Ping\xff\xf0");
+        let mut telnet = telnet_from(MockStream::new(data), 64);
+        telnet.set_max_subnegotiation_len(Some(64));
+        telnet.set_subnegotiation_limit(TelnetOption::NAWS, 4);
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Error(TelnetError::SubnegotiationTooLong(TelnetOption::NAWS, 4))
+        ));
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"ok"));
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::Subnegotiation(TelnetOption::GMCP, ref data) if data.as_ref() == b"Core.Ping"
+        ));
+    }
+
+    #[test]
+    fn applies_connect_options() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = ConnectOptions {
+            buf_size: 128,
+            connect_timeout: Some(Duration::from_secs(5)),
+            read_timeout: Some(Duration::from_millis(10)),
+            nodelay: true,
+            keepalive: true,
+            initial_negotiations: vec![(Action::Do, TelnetOption::Echo)],
+        };
+        let mut telnet =
+            Telnet::connect_with_options(listener.local_addr().unwrap(), options.clone()).unwrap();
+        let (mut socket, _) = listener.accept().unwrap();
+
+        assert_eq!(telnet.buffer_capacity(), 128);
+        assert!(matches!(telnet.read().unwrap(), Event::TimedOut));
+
+        // IAC DO ECHO
+        let mut buf = [0; 3];
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0xff, 0xfd, 0x01]);
+
+        // The socket options, as set on the connection
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        options.apply(&stream).unwrap();
+        let socket = socket2::SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+    }
+
+    #[test]
+    fn sends_output_marking_banner() {
+        // IAC SB OUTMRK ACK IAC SE
+        let stream = MockStream::new(vec![0xff, 0xfa, 0x1b, 0x06, 0xff, 0xf0]);
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        telnet
+            .send_output_marking(outmrk::Marker::Top, "Welcome")
+            .unwrap();
+        // IAC SB OUTMRK 'T' "Welcome" IAC SE
+        assert_eq!(
+            *writes.borrow(),
+            vec![b"\xff\xfa\x1bTWelcome\xff\xf0".to_vec()]
+        );
+
+        let event = telnet.read().unwrap();
+        assert!(matches!(
+            event,
+            Event::OutputMarking(outmrk::OutputMarking::Ack)
+        ));
+    }
+
+    #[test]
+    fn converts_stream_into_telnet() {
+        let stream = MockStream::new(b"hello".to_vec());
+        #[cfg(feature = "zcstream")]
+        let stream = ZlibStream::from_stream(stream);
+        let boxed: Box<TStream> = Box::new(stream);
+
+        let mut telnet: Telnet = boxed.into();
+        assert_eq!(telnet.buffer_capacity(), DEFAULT_BUF_SIZE);
+        let event = telnet.read().unwrap();
+        assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"hello"));
+    }
+
+    #[test]
+    fn sets_stream_mode_only_when_it_changes() {
+        let stream = MockStream::new(b"abcdefghij".to_vec());
+        let mode_changes = stream.mode_changes();
+        let mut telnet = telnet_from(stream, 2);
+
+        // Blocking without timeout
+        for _ in 0..3 {
+            telnet.read().unwrap();
+        }
+        assert_eq!(mode_changes.get(), 2);
+
+        // Nonblocking, the timeout being the same
+        telnet.read_nonblocking().unwrap();
+        assert_eq!(mode_changes.get(), 3);
+
+        telnet.read().unwrap();
+        assert_eq!(mode_changes.get(), 4);
+    }
+
+    #[test]
+    fn reports_completed_negotiations() {
+        // IAC WILL ECHO, IAC WONT TRANSMIT-BINARY, IAC DO NAWS
+        let data = vec![0xff, 0xfb, 0x01, 0xff, 0xfc, 0x00, 0xff, 0xfd, 0x1f];
+        let mut telnet = telnet_from(MockStream::new(data), 16);
+        telnet.negotiate(&Action::Do, TelnetOption::Echo).unwrap();
+        telnet
+            .negotiate(&Action::Do, TelnetOption::TransmitBinary)
+            .unwrap();
+
+        let mut events = Vec::new();
+        while let Ok(event) = telnet.read() {
+            events.push(event);
+        }
+        assert_eq!(events.len(), 5);
+        assert!(matches!(
+            events[0],
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        assert!(matches!(
+            events[1],
+            Event::OptionEnabled(TelnetOption::Echo)
+        ));
+        assert!(matches!(
+            events[2],
+            Event::Negotiation(Action::Wont, TelnetOption::TransmitBinary)
+        ));
+        assert!(matches!(
+            events[3],
+            Event::OptionRefused(TelnetOption::TransmitBinary)
+        ));
+        // Not a reply to a request of ours
+        assert!(matches!(
+            events[4],
+            Event::Negotiation(Action::Do, TelnetOption::NAWS)
+        ));
+    }
+
+    #[test]
+    fn parses_escaped_data_back_to_original() {
+        // A xorshift generator, so that a failure can be replayed from its seed
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..200 {
+            // Plenty of IAC bytes, including runs of them
+            let len = usize::from(next().to_le_bytes()[0] % 64);
+            let original: Vec<u8> = (0..len)
+                .map(|_| match next() % 4 {
+                    0 | 1 => 0xff,
+                    _ => next().to_le_bytes()[0],
+                })
+                .collect();
+            let buf_size = usize::from(next().to_le_bytes()[0] % 8) + 1;
+
+            let stream = MockStream::new(Vec::new());
+            let writes = stream.writes();
+            let mut telnet = telnet_from(stream, buf_size);
+
+            // `write` escapes the same way as `format::data`
+            let escaped = format::data(&original);
+            telnet.write(&original).unwrap();
+            assert_eq!(writes.borrow().concat(), escaped);
+
+            telnet.feed(&escaped);
+            let mut parsed = Vec::new();
+            while let Some(event) = telnet.event_queue.take_event() {
+                match event {
+                    Event::Data(data) => parsed.extend_from_slice(&data),
+                    event => panic!("unexpected {:?} for {:?}", event, original),
+                }
+            }
+            assert_eq!(parsed, original, "buffer size {buf_size}");
+        }
+    }
+
+    #[test]
+    fn unescapes_iac_split_across_reads() {
+        // "A" IAC | IAC "B" | "C" IAC | IAC
+        let stream = MockStream::new(vec![0x41, 0xff, 0xff, 0x42, 0x43, 0xff, 0xff]);
+        let mut telnet = telnet_from(stream, 2);
+
+        let mut events = Vec::new();
+        while let Ok(event) = telnet.read() {
+            events.push(event);
+        }
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], Event::Data(ref data) if data.as_ref() == b"A"));
+        assert!(matches!(events[1], Event::Data(ref data) if data.as_ref() == [0xff, 0x42]));
+        assert!(matches!(events[2], Event::Data(ref data) if data.as_ref() == b"C"));
+        assert!(matches!(events[3], Event::Data(ref data) if data.as_ref() == [0xff]));
+    }
+
+    #[test]
+    fn unescapes_buffer_full_of_iac() {
+        for buf_size in 1..=4 {
+            let stream = MockStream::new(vec![0xff; 64]);
+            let mut telnet = telnet_from(stream, buf_size);
+
+            let mut parsed = Vec::new();
+            while let Ok(event) = telnet.read() {
+                match event {
+                    Event::Data(data) => parsed.extend_from_slice(&data),
+                    event => panic!("unexpected {:?}", event),
+                }
+            }
+            assert_eq!(parsed, vec![0xff; 32], "buffer size {buf_size}");
+        }
+    }
+
+    #[test]
+    fn read_with_passes_events_in_order() {
+        // "ab" IAC WILL ECHO "cd"
+        let stream = MockStream::new(b"ab\xff\xfb\x01cd".to_vec());
+        let mut telnet = telnet_from(stream, 64);
+
+        let mut events = Vec::new();
+        telnet
+            .read_with(|event| {
+                events.push(match event {
+                    EventRef::Data(data) => format!("data {data:?}"),
+                    EventRef::Other(event) => format!("{event:?}"),
+                });
+            })
+            .unwrap();
+        assert_eq!(
+            events,
+            ["data [97, 98]", "Negotiation(Will, Echo)", "data [99, 100]"]
+        );
+
+        let result = telnet.read_with(|_| panic!("no event expected"));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn read_into_reuses_vec() {
+        // "ab" IAC WILL ECHO, then "cd" in the next read
+        let stream = MockStream::new(b"ab\xff\xfb\x01cd".to_vec());
+        let mut telnet = telnet_from(stream, 5);
+
+        let mut events = Vec::new();
+        assert_eq!(telnet.read_into(&mut events).unwrap(), 2);
+        assert!(matches!(&events[0], Event::Data(data) if &**data == b"ab"));
+        assert!(matches!(
+            events[1],
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+
+        // Queued events are appended without reading
+        telnet.feed(b"x");
+        assert_eq!(telnet.read_into(&mut events).unwrap(), 1);
+        assert!(matches!(&events[2], Event::Data(data) if &**data == b"x"));
+
+        // Appended after the events of the earlier reads
+        assert_eq!(telnet.read_into(&mut events).unwrap(), 1);
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[3], Event::Data(data) if &**data == b"cd"));
+
+        let capacity = events.capacity();
+        events.clear();
+        let result = telnet.read_into(&mut events);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert!(events.is_empty());
+        assert_eq!(events.capacity(), capacity);
+    }
+
+    #[test]
+    fn read_into_returns_errors_as_io_errors() {
+        let mut telnet = telnet_from(MockStream::new([b"ab", GARBLED_TTYPE].concat()), 64);
+        telnet.set_errors_as_io_error(true);
+
+        // The data before the error is appended, and the events after it stay queued
+        let mut events = Vec::new();
+        assert!(telnet.read_into(&mut events).is_err());
+        assert_eq!(events.len(), 1);
+        assert_eq!(telnet.read_into(&mut events).unwrap(), 2);
+        assert!(matches!(
+            events[1],
+            Event::Subnegotiation(TelnetOption::TTYPE, ref data) if data.as_ref() == b"\x00vt100"
+        ));
+        assert!(matches!(&events[2], Event::Data(data) if &**data == b"ok"));
+    }
+
+    #[test]
+    fn read_with_passes_reassembled_data() {
+        // "é" split between a read and the next
+        let stream = MockStream::new(b"\xa9".to_vec());
+        let mut telnet = telnet_from(stream, 64);
+        telnet.set_utf8_reassembly(true);
+        telnet.feed(b"a\xc3");
+
+        let mut data = Vec::new();
+        telnet
+            .read_with(|event| match event {
+                EventRef::Data(bytes) => data.extend_from_slice(bytes),
+                EventRef::Other(event) => panic!("unexpected {:?}", event),
+            })
+            .unwrap();
+        assert_eq!(data, b"a\xc3\xa9");
+    }
+
+    #[test]
+    fn keeps_event_boundaries_around_commands() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 64);
+        // "ab" IAC WILL ECHO "cd" IAC IAC "e" IAC NOP "f"
+        telnet.feed(b"ab\xff\xfb\x01cd\xff\xffe\xff\xf1f");
+
+        let mut events = Vec::new();
+        while let Some(event) = telnet.event_queue.take_event() {
+            events.push(event);
+        }
+        assert_eq!(events.len(), 6);
+        assert!(matches!(events[0], Event::Data(ref data) if data.as_ref() == b"ab"));
+        assert!(matches!(
+            events[1],
+            Event::Negotiation(Action::Will, TelnetOption::Echo)
+        ));
+        assert!(matches!(events[2], Event::Data(ref data) if data.as_ref() == b"cd"));
+        assert!(matches!(events[3], Event::Data(ref data) if data.as_ref() == b"\xffe"));
+        assert!(matches!(events[4], Event::UnknownIAC(0xf1)));
+        assert!(matches!(events[5], Event::Data(ref data) if data.as_ref() == b"f"));
+    }
+
+    #[test]
+    fn unescapes_iac_in_place() {
+        let mut telnet = telnet_from(MockStream::new(Vec::new()), 4);
+        // IAC | IAC "x" IAC | IAC IAC WILL | ECHO
+        let data = feed_data(&mut telnet, &[b"\xff", b"\xffx\xff", b"\xff\xff\xfb"]);
+        assert_eq!(data, b"\xffx\xff");
+
+        telnet.feed(b"\x01");
+        assert!(matches!(
+            telnet.event_queue.take_event(),
+            Some(Event::Negotiation(Action::Will, TelnetOption::Echo))
+        ));
+    }
+
+    #[test]
+    fn writes_chunks_escaping_iac_at_boundaries() {
+        let stream = MockStream::new(Vec::new());
+        let writes = stream.writes();
+        let mut telnet = telnet_from(stream, 16);
+
+        let chunks: [&[u8]; 3] = [b"ab\xff", b"\xffcd", b""];
+        assert_eq!(telnet.write_chunks(chunks).unwrap(), 6);
+        assert_eq!(*writes.borrow(), vec![b"ab\xff\xff\xff\xffcd".to_vec()]);
+    }
+
+    #[test]
+    fn calls_unknown_iac_handler() {
+        // IAC 0x10 'a' 'b' "cd", IAC 0x11 "ef", split by the buffer size
+        let stream = MockStream::new(b"\xff\x10abcd\xff\x11ef".to_vec());
+        let mut telnet = telnet_from(stream, 3);
+
+        let handled = Rc::new(RefCell::new(Vec::new()));
+        let commands = Rc::clone(&handled);
+        telnet.set_unknown_iac_handler(move |command, telnet| {
+            commands.borrow_mut().push(command);
+            if command == 0x10 {
+                telnet.consume_command_params(2);
+            }
+        });
+
+        let mut events = Vec::new();
+        while let Ok(event) = telnet.read() {
+            events.push(event);
+        }
+        assert_eq!(*handled.borrow(), [0x10, 0x11]);
+        assert_eq!(events.len(), 4);
+        assert!(
+            matches!(events[0], Event::RawCommand(ref command) if command.as_ref() == b"\x10ab")
+        );
+        assert!(matches!(events[1], Event::Data(ref data) if data.as_ref() == b"cd"));
+        assert!(matches!(events[2], Event::Data(ref data) if data.as_ref() == b"e"));
+        assert!(matches!(events[3], Event::Data(ref data) if data.as_ref() == b"f"));
+    }
 }
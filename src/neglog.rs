@@ -0,0 +1,56 @@
+// A bounded record of the last negotiations, for diagnostics
+
+use crate::{Action, TelnetOption};
+use std::collections::VecDeque;
+
+/// Whether a logged negotiation was sent or received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A negotiation or subnegotiation recorded by `Telnet::negotiation_log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoggedNegotiation {
+    Negotiation(Direction, Action, TelnetOption),
+    Subnegotiation(Direction, TelnetOption, Box<[u8]>),
+}
+
+pub struct NegotiationLog {
+    entries: VecDeque<LoggedNegotiation>,
+    capacity: usize,
+}
+
+impl NegotiationLog {
+    pub fn new() -> NegotiationLog {
+        NegotiationLog {
+            entries: VecDeque::new(),
+            capacity: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let excess = self.entries.len().saturating_sub(capacity);
+        self.entries.drain(..excess);
+    }
+
+    pub fn push(&mut self, entry: LoggedNegotiation) {
+        if !self.is_enabled() {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> &VecDeque<LoggedNegotiation> {
+        &self.entries
+    }
+}
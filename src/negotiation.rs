@@ -1,9 +1,13 @@
 // This implements the Q method described in Section 7 of RFC 1143
 
-use crate::byte::{BYTE_DO, BYTE_DONT, BYTE_WILL, BYTE_WONT};
+use crate::{
+    byte::{BYTE_DO, BYTE_DONT, BYTE_WILL, BYTE_WONT},
+    TelnetOption,
+};
+use std::collections::BTreeMap;
 
 /// Actions for telnet negotiation.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Action {
     Will,
     Wont,
@@ -12,6 +16,18 @@ pub enum Action {
 }
 
 impl Action {
+    /// Parses a negotiation command byte, returning `None` if it is not one.
+    #[allow(clippy::must_use_candidate)]
+    pub fn parse(byte: u8) -> Option<Action> {
+        match byte {
+            BYTE_WILL => Some(Action::Will),
+            BYTE_WONT => Some(Action::Wont),
+            BYTE_DO => Some(Action::Do),
+            BYTE_DONT => Some(Action::Dont),
+            _ => None,
+        }
+    }
+
     #[allow(clippy::must_use_candidate)]
     pub fn as_byte(&self) -> u8 {
         match *self {
@@ -21,4 +37,164 @@ impl Action {
             Action::Dont => BYTE_DONT,
         }
     }
+
+    /// The reply agreeing to this request, or `None` if no reply is expected.
+    #[allow(clippy::must_use_candidate)]
+    pub fn accept(&self) -> Option<Action> {
+        match *self {
+            Action::Will => Some(Action::Do),
+            Action::Do => Some(Action::Will),
+            Action::Wont | Action::Dont => None,
+        }
+    }
+
+    /// The reply refusing this request, or `None` if no reply is expected.
+    #[allow(clippy::must_use_candidate)]
+    pub fn refuse(&self) -> Option<Action> {
+        match *self {
+            Action::Will => Some(Action::Dont),
+            Action::Do => Some(Action::Wont),
+            Action::Wont | Action::Dont => None,
+        }
+    }
+}
+
+// The state of one side of an option
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum QState {
+    #[default]
+    No,
+    Yes,
+    // We asked to enable or disable it, and are waiting for the reply
+    WantNo,
+    WantYes,
+    // The remote host asked to enable it, and is waiting for our reply
+    PeerWantYes,
+}
+
+impl QState {
+    fn sent(self, enable: bool) -> QState {
+        match (self, enable) {
+            (QState::No | QState::WantNo | QState::WantYes, true) => QState::WantYes,
+            (QState::Yes | QState::PeerWantYes, true) => QState::Yes,
+            (QState::Yes | QState::WantNo | QState::WantYes, false) => QState::WantNo,
+            (QState::No | QState::PeerWantYes, false) => QState::No,
+        }
+    }
+
+    fn received(self, enable: bool) -> QState {
+        match (self, enable) {
+            (QState::WantYes | QState::Yes, true) => QState::Yes,
+            (QState::No | QState::PeerWantYes, true) => QState::PeerWantYes,
+            (QState::WantNo, true) | (_, false) => QState::No,
+        }
+    }
+}
+
+// The states of both sides of an option: (us, him)
+#[derive(Debug, Default, Clone, Copy)]
+struct OptionState {
+    us: QState,
+    him: QState,
+}
+
+/// Tracks the state of every negotiated option, following the Q method of RFC 1143.
+///
+/// Unlike the full Q method, it does not answer the remote host by itself. It only observes the
+/// negotiations sent and received, so that the pending ones can be reported.
+#[derive(Debug, Default)]
+pub struct OptionStates(BTreeMap<u8, OptionState>);
+
+impl OptionStates {
+    pub fn new() -> OptionStates {
+        OptionStates::default()
+    }
+
+    /// Records a negotiation sent to the remote host.
+    pub fn sent(&mut self, action: Action, opt: TelnetOption) {
+        let state = self.0.entry(opt.as_byte()).or_default();
+        match action {
+            Action::Will => state.us = state.us.sent(true),
+            Action::Wont => state.us = state.us.sent(false),
+            Action::Do => state.him = state.him.sent(true),
+            Action::Dont => state.him = state.him.sent(false),
+        }
+    }
+
+    /// Whether sending the negotiation would have no effect, because the option is already in
+    /// the requested state, or a request for it is waiting for a reply.
+    pub fn is_redundant(&self, action: Action, opt: TelnetOption) -> bool {
+        let state = self.0.get(&opt.as_byte()).copied().unwrap_or_default();
+        match action {
+            Action::Will => matches!(state.us, QState::Yes | QState::WantYes),
+            Action::Wont => matches!(state.us, QState::No | QState::WantNo),
+            Action::Do => matches!(state.him, QState::Yes | QState::WantYes),
+            Action::Dont => matches!(state.him, QState::No | QState::WantNo),
+        }
+    }
+
+    /// Whether a request received from the remote host to enable the option has not been answered.
+    pub fn is_awaiting_reply(&self, received: Action, opt: TelnetOption) -> bool {
+        let state = self.0.get(&opt.as_byte()).copied().unwrap_or_default();
+        match received {
+            Action::Will => state.him == QState::PeerWantYes,
+            Action::Do => state.us == QState::PeerWantYes,
+            Action::Wont | Action::Dont => false,
+        }
+    }
+
+    /// Records a negotiation received from the remote host.
+    ///
+    /// Returns whether the option was enabled if this answers our request to enable it, or `None`
+    /// otherwise.
+    pub fn received(&mut self, action: Action, opt: TelnetOption) -> Option<bool> {
+        let state = self.0.entry(opt.as_byte()).or_default();
+        let side = match action {
+            Action::Will | Action::Wont => &mut state.him,
+            Action::Do | Action::Dont => &mut state.us,
+        };
+        let requested = *side == QState::WantYes;
+        *side = side.received(matches!(action, Action::Will | Action::Do));
+        requested.then_some(*side == QState::Yes)
+    }
+
+    /// Whether both sides agreed that either of them uses the option.
+    pub fn is_enabled(&self, opt: TelnetOption) -> bool {
+        self.0
+            .get(&opt.as_byte())
+            .is_some_and(|state| state.us == QState::Yes || state.him == QState::Yes)
+    }
+
+    /// Whether both sides agreed that we use the option.
+    pub fn is_enabled_locally(&self, opt: TelnetOption) -> bool {
+        self.0
+            .get(&opt.as_byte())
+            .is_some_and(|state| state.us == QState::Yes)
+    }
+
+    /// Whether both sides agreed that the remote host uses the option.
+    pub fn is_enabled_remotely(&self, opt: TelnetOption) -> bool {
+        self.0
+            .get(&opt.as_byte())
+            .is_some_and(|state| state.him == QState::Yes)
+    }
+
+    /// The negotiations sent which have not been replied to yet, ordered by option.
+    pub fn pending(&self) -> Vec<(Action, TelnetOption)> {
+        let mut pending = Vec::new();
+        for (&byte, state) in &self.0 {
+            let opt = TelnetOption::parse(byte);
+            match state.us {
+                QState::WantYes => pending.push((Action::Will, opt)),
+                QState::WantNo => pending.push((Action::Wont, opt)),
+                _ => {}
+            }
+            match state.him {
+                QState::WantYes => pending.push((Action::Do, opt)),
+                QState::WantNo => pending.push((Action::Dont, opt)),
+                _ => {}
+            }
+        }
+        pending
+    }
 }
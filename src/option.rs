@@ -2,9 +2,16 @@
 
 // A macro to reduce the code duplication in the definition of TelnetOption
 macro_rules! telnet_options {
-    ($($byt:expr => $tno:ident),+) => {
+    ($($byt:expr => $tno:ident as $cst:ident),+) => {
+        /// The bytes of the known telnet options, for code which works with bytes rather than
+        /// [`TelnetOption`](crate::TelnetOption). Each constant is named after its variant, in
+        /// upper snake case.
+        pub mod opt {
+            $(pub const $cst: u8 = $byt;)+
+        }
+
         /// Telnet options
-        #[derive(Debug, Clone, Copy)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
         pub enum TelnetOption {
             $($tno,)+
             UnknownOption(u8),
@@ -24,54 +31,144 @@ macro_rules! telnet_options {
                     TelnetOption::UnknownOption(byte) => byte
                 }
             }
+
+            /// Finds an option by its variant name, ignoring case, for example when reading a
+            /// config file.
+            ///
+            /// A number such as `"42"` gives the option of that byte, which is an
+            /// `UnknownOption` unless the byte is known. Any other string gives `None`.
+            pub fn from_name(name: &str) -> Option<TelnetOption> {
+                $(if name.eq_ignore_ascii_case(stringify!($tno)) {
+                    return Some(TelnetOption::$tno);
+                })+
+                name.parse().ok().map(TelnetOption::parse)
+            }
+        }
+
+        // Options are ordered by their byte. An `UnknownOption` holding the byte of a known
+        // option is ordered right after it, to stay consistent with `Eq`.
+        impl Ord for TelnetOption {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                let is_unknown = |opt: &Self| matches!(opt, TelnetOption::UnknownOption(_));
+                self.as_byte()
+                    .cmp(&other.as_byte())
+                    .then_with(|| is_unknown(self).cmp(&is_unknown(other)))
+            }
+        }
+
+        impl PartialOrd for TelnetOption {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
         }
     }
 }
 
 telnet_options!(
-    0 => TransmitBinary,
-    1 => Echo,
-    2 => Reconnection,
-    3 => SuppressGoAhead,
-    4 => ApproxMessageSizeNeg,
-    5 => Status,
-    6 => TimingMark,
-    7 => RCTE,
-    8 => OutLineWidth,
-    9 => OutPageSize,
-    10 => NAOCRD,
-    11 => NAOHTS,
-    12 => NAOHTD,
-    13 => NAOFFD,
-    14 => NAOVTS,
-    15 => NAOVTD,
-    16 => NAOLFD,
-    17 => XASCII,
-    18 => Logout,
-    19 => ByteMacro,
-    20 => DET,
-    21 => SUPDUP,
-    22 => SUPDUPOutput,
-    23 => SNDLOC,
-    24 => TTYPE,
-    25 => EOR,
-    26 => TUID,
-    27 => OUTMRK,
-    28 => TTYLOC,
-    29 => OPT3270Regime,
-    30 => X3PAD,
-    31 => NAWS,
-    32 => TSPEED,
-    33 => LFLOW,
-    34 => Linemode,
-    35 => XDISPLOC,
-    36 => Environment,
-    37 => Authentication,
-    38 => Encryption,
-    39 => NewEnvironment,
-    70 => MSSP,
-    85 => Compress,
-    86 => Compress2,
-    93 => ZMP,
-    255 => EXOPL
+    0 => TransmitBinary as TRANSMIT_BINARY,
+    1 => Echo as ECHO,
+    2 => Reconnection as RECONNECTION,
+    3 => SuppressGoAhead as SUPPRESS_GO_AHEAD,
+    4 => ApproxMessageSizeNeg as APPROX_MESSAGE_SIZE_NEG,
+    5 => Status as STATUS,
+    6 => TimingMark as TIMING_MARK,
+    7 => RCTE as RCTE,
+    8 => OutLineWidth as OUT_LINE_WIDTH,
+    9 => OutPageSize as OUT_PAGE_SIZE,
+    10 => NAOCRD as NAOCRD,
+    11 => NAOHTS as NAOHTS,
+    12 => NAOHTD as NAOHTD,
+    13 => NAOFFD as NAOFFD,
+    14 => NAOVTS as NAOVTS,
+    15 => NAOVTD as NAOVTD,
+    16 => NAOLFD as NAOLFD,
+    17 => XASCII as XASCII,
+    18 => Logout as LOGOUT,
+    19 => ByteMacro as BYTE_MACRO,
+    20 => DET as DET,
+    21 => SUPDUP as SUPDUP,
+    22 => SUPDUPOutput as SUPDUP_OUTPUT,
+    23 => SNDLOC as SNDLOC,
+    24 => TTYPE as TTYPE,
+    25 => EOR as EOR,
+    26 => TUID as TUID,
+    27 => OUTMRK as OUTMRK,
+    28 => TTYLOC as TTYLOC,
+    29 => OPT3270Regime as OPT3270_REGIME,
+    30 => X3PAD as X3PAD,
+    31 => NAWS as NAWS,
+    32 => TSPEED as TSPEED,
+    33 => LFLOW as LFLOW,
+    34 => Linemode as LINEMODE,
+    35 => XDISPLOC as XDISPLOC,
+    36 => Environment as ENVIRONMENT,
+    37 => Authentication as AUTHENTICATION,
+    38 => Encryption as ENCRYPTION,
+    39 => NewEnvironment as NEW_ENVIRONMENT,
+    70 => MSSP as MSSP,
+    85 => Compress as COMPRESS,
+    86 => Compress2 as COMPRESS2,
+    93 => ZMP as ZMP,
+    201 => GMCP as GMCP,
+    255 => EXOPL as EXOPL
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn finds_options_by_name() {
+        assert_eq!(TelnetOption::from_name("naws"), Some(TelnetOption::NAWS));
+        assert_eq!(TelnetOption::from_name("Echo"), Some(TelnetOption::Echo));
+        assert_eq!(TelnetOption::from_name("TTYPE"), Some(TelnetOption::TTYPE));
+        assert_eq!(TelnetOption::from_name("tty"), None);
+        assert_eq!(TelnetOption::from_name(""), None);
+        assert_eq!(
+            TelnetOption::from_name("42"),
+            Some(TelnetOption::UnknownOption(42))
+        );
+        assert_eq!(TelnetOption::from_name("31"), Some(TelnetOption::NAWS));
+        assert_eq!(TelnetOption::from_name("256"), None);
+    }
+
+    #[test]
+    fn mirrors_options_as_bytes() {
+        assert_eq!(opt::NAWS, TelnetOption::NAWS.as_byte());
+        assert_eq!(opt::ECHO, TelnetOption::Echo.as_byte());
+        assert_eq!(
+            opt::SUPPRESS_GO_AHEAD,
+            TelnetOption::SuppressGoAhead.as_byte()
+        );
+        assert_eq!(opt::EXOPL, TelnetOption::EXOPL.as_byte());
+    }
+
+    #[test]
+    fn orders_by_byte() {
+        let mut map = BTreeMap::new();
+        for opt in [
+            TelnetOption::GMCP,
+            TelnetOption::UnknownOption(100),
+            TelnetOption::Echo,
+            TelnetOption::NAWS,
+            TelnetOption::UnknownOption(1),
+            TelnetOption::TransmitBinary,
+        ] {
+            map.insert(opt, opt.as_byte());
+        }
+
+        let keys: Vec<_> = map.into_keys().collect();
+        assert_eq!(
+            keys,
+            [
+                TelnetOption::TransmitBinary,
+                TelnetOption::Echo,
+                TelnetOption::UnknownOption(1),
+                TelnetOption::NAWS,
+                TelnetOption::UnknownOption(100),
+                TelnetOption::GMCP,
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Encoding and decoding of the Output Marking option (OUTMRK, RFC 933).
+
+const ACK: u8 = 6;
+const NAK: u8 = 21;
+
+/// Where the banner of an OUTMRK subnegotiation is displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+    /// Anywhere
+    General,
+    /// The top of the screen
+    Top,
+    /// The bottom of the screen
+    Bottom,
+    /// The left of the screen
+    Left,
+    /// The right of the screen
+    Right,
+}
+
+impl Marker {
+    #[allow(clippy::must_use_candidate)]
+    pub fn parse(byte: u8) -> Option<Marker> {
+        match byte {
+            b'G' => Some(Marker::General),
+            b'T' => Some(Marker::Top),
+            b'B' => Some(Marker::Bottom),
+            b'L' => Some(Marker::Left),
+            b'R' => Some(Marker::Right),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::must_use_candidate)]
+    pub fn as_byte(&self) -> u8 {
+        match *self {
+            Marker::General => b'G',
+            Marker::Top => b'T',
+            Marker::Bottom => b'B',
+            Marker::Left => b'L',
+            Marker::Right => b'R',
+        }
+    }
+}
+
+/// A command carried by an OUTMRK subnegotiation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputMarking {
+    /// A banner sent by the server, with where to display it
+    Banner(Marker, String),
+    /// The user side accepts to display the banners
+    Ack,
+    /// The user side refuses to display the banners
+    Nak,
+}
+
+/// Encodes a banner into the payload of an OUTMRK subnegotiation.
+///
+/// IAC bytes are not escaped here, as that is done when the subnegotiation is sent.
+#[allow(clippy::must_use_candidate)]
+pub fn encode(marker: Marker, banner: &str) -> Vec<u8> {
+    let mut data = Vec::with_capacity(banner.len() + 1);
+    data.push(marker.as_byte());
+    data.extend_from_slice(banner.as_bytes());
+    data
+}
+
+/// Decodes the payload of an OUTMRK subnegotiation, or returns `None` if it is malformed. Bytes
+/// of a banner which are not valid UTF-8 are replaced with `U+FFFD`.
+#[allow(clippy::must_use_candidate)]
+pub fn parse(data: &[u8]) -> Option<OutputMarking> {
+    match data {
+        [ACK] => Some(OutputMarking::Ack),
+        [NAK] => Some(OutputMarking::Nak),
+        [marker, banner @ ..] => Some(OutputMarking::Banner(
+            Marker::parse(*marker)?,
+            String::from_utf8_lossy(banner).into_owned(),
+        )),
+        [] => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_parses_banner() {
+        let data = encode(Marker::Top, "MULTICS");
+        assert_eq!(data, b"TMULTICS");
+        assert_eq!(
+            parse(&data),
+            Some(OutputMarking::Banner(Marker::Top, "MULTICS".to_string()))
+        );
+
+        assert_eq!(parse(&[ACK]), Some(OutputMarking::Ack));
+        assert_eq!(parse(b"Xbanner"), None);
+        assert_eq!(parse(&[]), None);
+    }
+}
@@ -0,0 +1,81 @@
+//! Decoding of the Remote Controlled Transmission and Echoing option (RCTE, RFC 726).
+
+const PRINT_BREAK: u8 = 1;
+const PRINT_TEXT: u8 = 2;
+const BREAK_CLASSES: u8 = 4;
+const TRANSMIT_CLASSES: u8 = 8;
+
+/// A command carried by an RCTE subnegotiation.
+///
+/// The character classes are masks of 12 bits as sent on the wire, the two bytes being read
+/// big-endian. A class is left unchanged when `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RcteCmd {
+    /// Whether the receiver echoes the break character
+    pub print_break: bool,
+    /// Whether the receiver echoes the text up to the break character
+    pub print_text: bool,
+    /// The new classes of break characters
+    pub break_classes: Option<u16>,
+    /// The new classes of characters which cause a transmission
+    pub transmit_classes: Option<u16>,
+}
+
+/// Decodes the payload of an RCTE subnegotiation, or returns `None` if it is malformed.
+#[allow(clippy::must_use_candidate)]
+pub fn parse(data: &[u8]) -> Option<RcteCmd> {
+    let (&cmd, mut rest) = data.split_first()?;
+    let mut classes = |present: bool| -> Option<Option<u16>> {
+        if !present {
+            return Some(None);
+        }
+        let (mask, tail) = rest.split_at_checked(2)?;
+        rest = tail;
+        Some(Some(u16::from_be_bytes([mask[0], mask[1]])))
+    };
+
+    let break_classes = classes(cmd & BREAK_CLASSES != 0)?;
+    let transmit_classes = classes(cmd & TRANSMIT_CLASSES != 0)?;
+    if !rest.is_empty() {
+        return None;
+    }
+
+    Some(RcteCmd {
+        print_break: cmd & PRINT_BREAK != 0,
+        print_text: cmd & PRINT_TEXT != 0,
+        break_classes,
+        transmit_classes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rcte_commands() {
+        assert_eq!(
+            parse(&[0x0f, 0x0f, 0xff, 0x00, 0x01]),
+            Some(RcteCmd {
+                print_break: true,
+                print_text: true,
+                break_classes: Some(0x0fff),
+                transmit_classes: Some(0x0001),
+            })
+        );
+        assert_eq!(
+            parse(&[0x02]),
+            Some(RcteCmd {
+                print_break: false,
+                print_text: true,
+                break_classes: None,
+                transmit_classes: None,
+            })
+        );
+
+        // Missing or extra class bytes
+        assert_eq!(parse(&[0x04, 0x0f]), None);
+        assert_eq!(parse(&[0x00, 0x0f]), None);
+        assert_eq!(parse(&[]), None);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::{Event, Telnet};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, ErrorKind, Read},
+};
+
+/// The data received by a `Telnet`, as a [`BufRead`]. See [`Telnet::data_reader`].
+///
+/// Only the payload of [`Event::Data`] is read. The other events met while filling the buffer
+/// are set aside, in order, to be taken with [`DataReader::take_event`]. When dropped, the data
+/// not consumed yet and the events not taken are queued again on the `Telnet`, to be returned by
+/// its next reads.
+pub struct DataReader<'a> {
+    telnet: &'a mut Telnet,
+    data: Box<[u8]>,
+    pos: usize,
+    events: VecDeque<Event>,
+}
+
+impl<'a> DataReader<'a> {
+    pub(crate) fn new(telnet: &'a mut Telnet) -> DataReader<'a> {
+        DataReader {
+            telnet,
+            data: Box::default(),
+            pos: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Takes the oldest event other than data set aside while reading.
+    pub fn take_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+}
+
+impl Read for DataReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = {
+            let data = self.fill_buf()?;
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            len
+        };
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for DataReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos >= self.data.len() {
+            match self.telnet.read() {
+                Ok(Event::Data(data)) => {
+                    self.data = data;
+                    self.pos = 0;
+                }
+                Ok(Event::TimedOut) => {
+                    return Err(io::Error::new(ErrorKind::TimedOut, "read timed out"));
+                }
+                Ok(event) => self.events.push_back(event),
+                // The end of the data
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(&[]),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(&self.data[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.data.len());
+    }
+}
+
+impl Drop for DataReader<'_> {
+    fn drop(&mut self) {
+        // The events set aside came before the data left in the buffer
+        let mut kept: Vec<_> = self.events.drain(..).collect();
+        if self.pos < self.data.len() {
+            kept.push(Event::Data(Box::from(&self.data[self.pos..])));
+        }
+        self.telnet.requeue_front(kept);
+    }
+}
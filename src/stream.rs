@@ -1,13 +1,87 @@
+use socket2::SockRef;
+#[cfg(unix)]
+use std::convert::TryFrom;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
 use std::{
-    io::{Read, Result, Write},
-    net::TcpStream,
+    io::{Error, ErrorKind, Read, Result, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
     time::Duration,
 };
 
+/// A stream which a telnet connection can be made over.
+///
+/// Only [`Stream::set_nonblocking`] and [`Stream::set_read_timeout`] must be implemented. The other
+/// methods control socket features which not every stream has, and return an
+/// [`ErrorKind::Unsupported`] error unless implemented.
 #[allow(clippy::missing_errors_doc)]
 pub trait Stream: Read + Write {
     fn set_nonblocking(&self, nonblocking: bool) -> Result<()>;
     fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()>;
+
+    fn set_write_timeout(&self, _dur: Option<Duration>) -> Result<()> {
+        Err(unsupported())
+    }
+
+    fn write_timeout(&self) -> Result<Option<Duration>> {
+        Err(unsupported())
+    }
+
+    fn set_nodelay(&self, _nodelay: bool) -> Result<()> {
+        Err(unsupported())
+    }
+
+    fn shutdown(&self, _how: Shutdown) -> Result<()> {
+        Err(unsupported())
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        Err(unsupported())
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Err(unsupported())
+    }
+
+    /// The number of bytes which can be read without blocking.
+    fn available(&self) -> Result<usize> {
+        Err(unsupported())
+    }
+
+    /// Holds back partial packets until uncorked, as `TCP_CORK` does.
+    fn set_cork(&self, _cork: bool) -> Result<()> {
+        Err(unsupported())
+    }
+
+    /// Takes the pending error of the underlying socket, such as `SO_ERROR`, if there is one.
+    ///
+    /// Streams which are not sockets have no such error, and return `Ok(None)`.
+    fn take_error(&self) -> Result<Option<Error>> {
+        Ok(None)
+    }
+
+    /// How long closing the stream waits for unsent data to go out, as `SO_LINGER` does.
+    fn set_linger(&self, _linger: Option<Duration>) -> Result<()> {
+        Err(unsupported())
+    }
+
+    /// The raw file descriptor of the underlying socket, if there is one.
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// The raw socket of the underlying socket, if there is one.
+    #[cfg(windows)]
+    fn as_raw_socket(&self) -> Option<RawSocket> {
+        None
+    }
+}
+
+fn unsupported() -> Error {
+    Error::new(ErrorKind::Unsupported, "not supported by this stream")
 }
 
 impl Stream for TcpStream {
@@ -18,4 +92,140 @@ impl Stream for TcpStream {
     fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
         self.set_read_timeout(dur)
     }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        self.set_write_timeout(dur)
+    }
+
+    fn write_timeout(&self) -> Result<Option<Duration>> {
+        self.write_timeout()
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+        self.set_nodelay(nodelay)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.shutdown(how)
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        self.peer_addr()
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.local_addr()
+    }
+
+    // Peeks without blocking, then puts the mode back. The mode cannot be read on Windows.
+    // Asked with `FIONREAD`, which socket2 does not offer
+    #[cfg(unix)]
+    fn available(&self) -> Result<usize> {
+        let mut count: libc::c_int = 0;
+        // SAFETY: the descriptor is open as long as `self`, and FIONREAD writes a `c_int`
+        if unsafe { libc::ioctl(AsRawFd::as_raw_fd(self), libc::FIONREAD, &mut count) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        Ok(usize::try_from(count).unwrap_or(0))
+    }
+
+    #[cfg(windows)]
+    #[allow(clippy::cast_possible_truncation)]
+    fn available(&self) -> Result<usize> {
+        use windows_sys::Win32::Networking::WinSock::{ioctlsocket, FIONREAD, SOCKET};
+
+        let mut count: u32 = 0;
+        let socket = AsRawSocket::as_raw_socket(self) as SOCKET;
+        // SAFETY: the socket is open as long as `self`, and FIONREAD writes a `u32`
+        if unsafe { ioctlsocket(socket, FIONREAD, &mut count) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(count as usize)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn set_cork(&self, cork: bool) -> Result<()> {
+        SockRef::from(self).set_cork(cork)
+    }
+
+    fn take_error(&self) -> Result<Option<Error>> {
+        self.take_error()
+    }
+
+    fn set_linger(&self, linger: Option<Duration>) -> Result<()> {
+        // Rounded up to whole seconds, as socket2 truncates and zero would reset the connection
+        let linger = linger
+            .map(|dur| Duration::from_secs(dur.as_secs() + u64::from(dur.subsec_nanos() > 0)));
+        SockRef::from(self).set_linger(linger)
+    }
+
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        Some(AsRawFd::as_raw_fd(self))
+    }
+
+    #[cfg(windows)]
+    fn as_raw_socket(&self) -> Option<RawSocket> {
+        Some(AsRawSocket::as_raw_socket(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Implements only what `Stream` requires
+    struct MinimalStream;
+
+    impl Read for MinimalStream {
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl Write for MinimalStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Stream for MinimalStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn optional_methods_are_unsupported_by_default() {
+        let stream = MinimalStream;
+        assert!(stream.set_nonblocking(true).is_ok());
+
+        let unsupported = |result: Result<()>| result.unwrap_err().kind() == ErrorKind::Unsupported;
+        assert!(unsupported(stream.set_write_timeout(None)));
+        assert_eq!(
+            stream.write_timeout().unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+        assert!(unsupported(stream.set_nodelay(true)));
+        assert!(unsupported(stream.shutdown(Shutdown::Both)));
+        assert_eq!(
+            stream.peer_addr().unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+        assert_eq!(
+            stream.available().unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+        assert!(unsupported(stream.set_cork(true)));
+        assert!(unsupported(stream.set_linger(None)));
+        assert!(stream.take_error().unwrap().is_none());
+    }
 }
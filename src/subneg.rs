@@ -0,0 +1,368 @@
+//! Decoding of subnegotiation payloads for the common options.
+//!
+//! [`decode`] routes a payload to the parser of its option, so that a consumer can `match` on
+//! the decoded result instead of handling the bytes of each option by hand.
+
+use crate::{
+    encryption::{self, EncryptCmd},
+    exopl::{self, ExtendedOption},
+    lflow::{self, LFlowCmd},
+    outmrk::{self, OutputMarking},
+    rcte::{self, RcteCmd},
+    tn3270::{self, RegimeCmd},
+    TelnetOption,
+};
+use std::collections::HashMap;
+
+const TTYPE_IS: u8 = 0;
+const TTYPE_SEND: u8 = 1;
+
+// The codes of the variable lists of NEW-ENVIRON (RFC 1572). Its IS and SEND, like those of
+// TSPEED (RFC 1079), are the codes of TTYPE.
+const ENV_VAR: u8 = 0;
+const ENV_VALUE: u8 = 1;
+const ENV_ESC: u8 = 2;
+const ENV_USERVAR: u8 = 3;
+
+const MSSP_VAR: u8 = 1;
+const MSSP_VAL: u8 = 2;
+
+/// A TTYPE (terminal type, RFC 1091) subnegotiation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminalType {
+    /// The name of the terminal type
+    Is(String),
+    /// A request for the name of the terminal type
+    Send,
+}
+
+/// A decoded subnegotiation payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedSubneg {
+    /// NAWS (RFC 1073): the window size in characters
+    WindowSize { width: u16, height: u16 },
+    /// TTYPE (RFC 1091)
+    TerminalType(TerminalType),
+    /// MSSP: the status variables of a MUD server, each with one or more values
+    Mssp(HashMap<String, Vec<String>>),
+    /// GMCP: a package name and its JSON data, which may be empty
+    Gmcp { package: String, data: String },
+    /// EXOPL (RFC 861)
+    ExtendedOption(ExtendedOption),
+    /// LFLOW (RFC 1372)
+    LFlow(LFlowCmd),
+    /// TN3270-REGIME (RFC 1041)
+    Tn3270Regime(RegimeCmd),
+    /// RCTE (RFC 726)
+    Rcte(RcteCmd),
+    /// OUTMRK (RFC 933)
+    OutputMarking(OutputMarking),
+    /// Encryption (RFC 2946)
+    Encryption(EncryptCmd),
+    /// An option without a decoder, or a malformed payload
+    Raw(Box<[u8]>),
+}
+
+/// Decodes the payload of a subnegotiation for `opt`.
+///
+/// Payloads of unknown options, and payloads which cannot be decoded, are returned as
+/// [`DecodedSubneg::Raw`].
+#[allow(clippy::must_use_candidate)]
+pub fn decode(opt: TelnetOption, data: &[u8]) -> DecodedSubneg {
+    let decoded = match opt {
+        TelnetOption::NAWS => naws(data),
+        TelnetOption::TTYPE => ttype(data).map(DecodedSubneg::TerminalType),
+        TelnetOption::MSSP => mssp(data).map(DecodedSubneg::Mssp),
+        TelnetOption::GMCP => Some(gmcp(data)),
+        TelnetOption::EXOPL => exopl::parse(data).map(DecodedSubneg::ExtendedOption),
+        TelnetOption::LFLOW => lflow::parse(data).map(DecodedSubneg::LFlow),
+        TelnetOption::OPT3270Regime => tn3270::parse(data).map(DecodedSubneg::Tn3270Regime),
+        TelnetOption::RCTE => rcte::parse(data).map(DecodedSubneg::Rcte),
+        TelnetOption::OUTMRK => outmrk::parse(data).map(DecodedSubneg::OutputMarking),
+        TelnetOption::Encryption => encryption::parse(data).map(DecodedSubneg::Encryption),
+        _ => None,
+    };
+    decoded.unwrap_or_else(|| DecodedSubneg::Raw(Box::from(data)))
+}
+
+/// Encodes MSSP status variables into a subnegotiation payload.
+///
+/// Each variable is followed by all of its values. IAC bytes are not escaped here, as that is
+/// done when the subnegotiation is sent.
+#[allow(clippy::must_use_candidate)]
+pub fn encode_mssp(vars: &[(&str, &[&str])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (name, values) in vars {
+        data.push(MSSP_VAR);
+        data.extend_from_slice(name.as_bytes());
+        for value in *values {
+            data.push(MSSP_VAL);
+            data.extend_from_slice(value.as_bytes());
+        }
+    }
+    data
+}
+
+/// The values used to answer the `SEND` requests of the remote host automatically.
+///
+/// Only the options with a configured value are answered, and only once they are enabled on our
+/// side; the requests for any other option are left to the caller. See [`Telnet::set_subnegotiation_policy`](crate::Telnet::set_subnegotiation_policy).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubnegotiationPolicy {
+    /// The answer to TTYPE `SEND`
+    pub terminal_type: Option<String>,
+    /// The window size sent with NAWS once the remote host asks for it with `DO NAWS`
+    pub window_size: Option<(u16, u16)>,
+    /// The transmit and receive speeds answered to TSPEED `SEND`
+    pub terminal_speed: Option<(u32, u32)>,
+    /// The variables answered to NEW-ENVIRON `SEND`. Empty means NEW-ENVIRON is not answered.
+    pub environment: Vec<(String, String)>,
+    /// Whether to answer a `SEND` for an option we have not agreed to use with `WILL`. Off by
+    /// default, so that the values are only sent to a remote host which negotiated the option.
+    pub answer_unsolicited: bool,
+}
+
+impl SubnegotiationPolicy {
+    // The payload of the `IS` reply to a subnegotiation, if it is a `SEND` this policy answers
+    pub(crate) fn reply(&self, opt: TelnetOption, data: &[u8]) -> Option<Vec<u8>> {
+        let (&TTYPE_SEND, requested) = data.split_first()? else {
+            return None;
+        };
+
+        let mut reply = vec![TTYPE_IS];
+        match opt {
+            TelnetOption::TTYPE => {
+                reply.extend_from_slice(self.terminal_type.as_ref()?.as_bytes());
+            }
+            TelnetOption::TSPEED => {
+                let (transmit, receive) = self.terminal_speed?;
+                reply.extend_from_slice(format!("{transmit},{receive}").as_bytes());
+            }
+            TelnetOption::NewEnvironment if !self.environment.is_empty() => {
+                let names = env_names(requested);
+                for (name, value) in &self.environment {
+                    if names.is_empty() || names.iter().any(|n| n == name.as_bytes()) {
+                        reply.push(ENV_VAR);
+                        env_escape_to(name.as_bytes(), &mut reply);
+                        reply.push(ENV_VALUE);
+                        env_escape_to(value.as_bytes(), &mut reply);
+                    }
+                }
+            }
+            _ => return None,
+        }
+        Some(reply)
+    }
+}
+
+// The names listed in a NEW-ENVIRON `SEND`, with their escapes removed. An empty list asks for
+// every variable.
+fn env_names(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut names = Vec::new();
+    let mut name = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            ENV_VAR | ENV_USERVAR => {
+                if !name.is_empty() {
+                    names.push(std::mem::take(&mut name));
+                }
+            }
+            ENV_ESC => name.extend(bytes.next()),
+            _ => name.push(byte),
+        }
+    }
+    if !name.is_empty() {
+        names.push(name);
+    }
+    names
+}
+
+fn env_escape_to(data: &[u8], buf: &mut Vec<u8>) {
+    for &byte in data {
+        if matches!(byte, ENV_VAR | ENV_VALUE | ENV_ESC | ENV_USERVAR) {
+            buf.push(ENV_ESC);
+        }
+        buf.push(byte);
+    }
+}
+
+fn naws(data: &[u8]) -> Option<DecodedSubneg> {
+    match *data {
+        [w1, w2, h1, h2] => Some(DecodedSubneg::WindowSize {
+            width: u16::from_be_bytes([w1, w2]),
+            height: u16::from_be_bytes([h1, h2]),
+        }),
+        _ => None,
+    }
+}
+
+pub(crate) fn ttype(data: &[u8]) -> Option<TerminalType> {
+    match data {
+        [TTYPE_IS, name @ ..] => Some(TerminalType::Is(String::from_utf8_lossy(name).into_owned())),
+        [TTYPE_SEND] => Some(TerminalType::Send),
+        _ => None,
+    }
+}
+
+fn mssp(data: &[u8]) -> Option<HashMap<String, Vec<String>>> {
+    let mut vars: HashMap<String, Vec<String>> = HashMap::new();
+    let mut name = None;
+
+    // Each field starts with a VAR or VAL byte and runs until the next one
+    let (&first, _) = data.split_first()?;
+    if first != MSSP_VAR {
+        return None;
+    }
+    let mut kind = first;
+    let mut start = 1;
+    for end in 1..=data.len() {
+        if end < data.len() && data[end] != MSSP_VAR && data[end] != MSSP_VAL {
+            continue;
+        }
+
+        let text = String::from_utf8_lossy(&data[start..end]).into_owned();
+        if kind == MSSP_VAR {
+            vars.entry(text.clone()).or_default();
+            name = Some(text);
+        } else {
+            vars.get_mut(name.as_ref()?)?.push(text);
+        }
+
+        if end < data.len() {
+            kind = data[end];
+            start = end + 1;
+        }
+    }
+
+    Some(vars)
+}
+
+fn gmcp(data: &[u8]) -> DecodedSubneg {
+    let text = String::from_utf8_lossy(data);
+    let (package, data) = text.split_once(' ').unwrap_or((&text, ""));
+    DecodedSubneg::Gmcp {
+        package: package.to_string(),
+        data: data.trim_start().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_naws() {
+        assert_eq!(
+            decode(TelnetOption::NAWS, &[0, 80, 0, 24]),
+            DecodedSubneg::WindowSize {
+                width: 80,
+                height: 24
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_ttype() {
+        assert_eq!(
+            decode(TelnetOption::TTYPE, b"\x00xterm"),
+            DecodedSubneg::TerminalType(TerminalType::Is("xterm".to_string()))
+        );
+        assert_eq!(
+            decode(TelnetOption::TTYPE, &[1]),
+            DecodedSubneg::TerminalType(TerminalType::Send)
+        );
+    }
+
+    #[test]
+    fn decodes_mssp() {
+        let decoded = decode(
+            TelnetOption::MSSP,
+            b"\x01NAME\x02Mud\x01PORT\x024000\x024001",
+        );
+
+        let mut expected = HashMap::new();
+        expected.insert("NAME".to_string(), vec!["Mud".to_string()]);
+        expected.insert(
+            "PORT".to_string(),
+            vec!["4000".to_string(), "4001".to_string()],
+        );
+        assert_eq!(decoded, DecodedSubneg::Mssp(expected));
+    }
+
+    #[test]
+    fn decodes_gmcp() {
+        assert_eq!(
+            decode(TelnetOption::GMCP, b"Core.Hello {\"client\":\"rs\"}"),
+            DecodedSubneg::Gmcp {
+                package: "Core.Hello".to_string(),
+                data: "{\"client\":\"rs\"}".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_payloads_raw() {
+        assert_eq!(
+            decode(TelnetOption::Echo, &[1, 2]),
+            DecodedSubneg::Raw(Box::new([1, 2]))
+        );
+        assert_eq!(
+            decode(TelnetOption::NAWS, &[1, 2]),
+            DecodedSubneg::Raw(Box::new([1, 2]))
+        );
+    }
+
+    #[test]
+    fn answers_only_configured_sends() {
+        let policy = SubnegotiationPolicy {
+            terminal_type: Some("xterm".to_string()),
+            ..SubnegotiationPolicy::default()
+        };
+
+        assert_eq!(
+            policy.reply(TelnetOption::TTYPE, &[1]),
+            Some(b"\x00xterm".to_vec())
+        );
+        assert_eq!(policy.reply(TelnetOption::TTYPE, b"\x00vt100"), None);
+        assert_eq!(policy.reply(TelnetOption::TSPEED, &[1]), None);
+        assert_eq!(policy.reply(TelnetOption::NewEnvironment, &[1]), None);
+    }
+
+    #[test]
+    fn answers_requested_environment_variables() {
+        let policy = SubnegotiationPolicy {
+            environment: vec![
+                ("USER".to_string(), "me".to_string()),
+                ("LANG".to_string(), "C".to_string()),
+            ],
+            ..SubnegotiationPolicy::default()
+        };
+
+        assert_eq!(
+            policy.reply(TelnetOption::NewEnvironment, &[1]),
+            Some(b"\x00\x00USER\x01me\x00LANG\x01C".to_vec())
+        );
+        assert_eq!(
+            policy.reply(TelnetOption::NewEnvironment, b"\x01\x00LANG"),
+            Some(b"\x00\x00LANG\x01C".to_vec())
+        );
+    }
+
+    #[test]
+    fn unescapes_requested_environment_variables() {
+        let policy = SubnegotiationPolicy {
+            environment: vec![
+                ("A\x00B".to_string(), "1".to_string()),
+                ("A".to_string(), "2".to_string()),
+            ],
+            ..SubnegotiationPolicy::default()
+        };
+
+        // SEND VAR "A" ESC VAR "B"
+        assert_eq!(
+            policy.reply(TelnetOption::NewEnvironment, b"\x01\x00A\x02\x00B"),
+            Some(b"\x00\x00A\x02\x00B\x011".to_vec())
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! Decoding of the 3270 Regime option (TN3270-REGIME, RFC 1041).
+
+const REGIME_IS: u8 = 0;
+const REGIME_ARE: u8 = 1;
+
+/// The name of the 3270 data stream regime. Any other name, such as `NVT`, means the connection
+/// carries an NVT data stream.
+pub const REGIME_3270: &str = "3270";
+
+/// A command carried by a TN3270-REGIME subnegotiation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegimeCmd {
+    /// The sender switches to the named regime
+    Is(String),
+    /// The sender asks the receiver to switch to the named regime
+    Are(String),
+}
+
+impl RegimeCmd {
+    /// The name of the regime.
+    #[allow(clippy::must_use_candidate)]
+    pub fn regime(&self) -> &str {
+        match self {
+            RegimeCmd::Is(name) | RegimeCmd::Are(name) => name,
+        }
+    }
+
+    /// Whether the regime is the 3270 data stream.
+    #[allow(clippy::must_use_candidate)]
+    pub fn is_3270(&self) -> bool {
+        self.regime().eq_ignore_ascii_case(REGIME_3270)
+    }
+}
+
+/// Decodes the payload of a TN3270-REGIME subnegotiation, or returns `None` if it is malformed.
+#[allow(clippy::must_use_candidate)]
+pub fn parse(data: &[u8]) -> Option<RegimeCmd> {
+    let (&cmd, name) = data.split_first()?;
+    let name = std::str::from_utf8(name).ok()?.to_string();
+    match cmd {
+        REGIME_IS => Some(RegimeCmd::Is(name)),
+        REGIME_ARE => Some(RegimeCmd::Are(name)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_regime_commands() {
+        let cmd = parse(b"\x003270").unwrap();
+        assert_eq!(cmd, RegimeCmd::Is("3270".to_string()));
+        assert!(cmd.is_3270());
+
+        let cmd = parse(b"\x01NVT").unwrap();
+        assert_eq!(cmd, RegimeCmd::Are("NVT".to_string()));
+        assert!(!cmd.is_3270());
+
+        assert_eq!(parse(b"\x023270"), None);
+        assert_eq!(parse(&[]), None);
+    }
+}
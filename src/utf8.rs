@@ -0,0 +1,25 @@
+// Helpers for keeping multibyte UTF-8 characters intact across reads
+
+/// Returns the length of an incomplete UTF-8 sequence at the end of `data`, or 0 if the data
+/// ends on a character boundary.
+pub fn incomplete_tail_len(data: &[u8]) -> usize {
+    // A UTF-8 character is at most 4 bytes, so only the last 3 bytes can be incomplete
+    for back in 1..=data.len().min(3) {
+        let byte = data[data.len() - back];
+
+        // Skip continuation bytes (0b10xx_xxxx)
+        if byte & 0xC0 == 0x80 {
+            continue;
+        }
+
+        let needed = match byte {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        return if back < needed { back } else { 0 };
+    }
+
+    0
+}
@@ -1,9 +1,35 @@
 use crate::stream::Stream;
+use std::io::{Error, ErrorKind, Result};
 
 /// Stream with ability to be upgraded to zlib stream.
 pub trait ZCStream: Stream {
     /// Begin zlib decompression on downstream. Ignored if already enabled.
     fn begin_zlib(&mut self);
+    /// Begin zlib decompression on downstream, with `input` as the first compressed bytes, for
+    /// the bytes which were read past the start of compression. Ignored if already enabled.
+    ///
+    /// By default, decompression begins without `input`, which is dropped. Streams used with
+    /// [`Telnet::set_auto_mccp`](crate::Telnet::set_auto_mccp) should implement it.
+    fn begin_zlib_with_input(&mut self, _input: &[u8]) {
+        self.begin_zlib();
+    }
+    /// Begin zlib decompression on downstream, using a preset dictionary. Ignored if already enabled.
+    ///
+    /// By default, this returns an [`ErrorKind::Unsupported`] error, and decompression does not
+    /// begin.
+    #[allow(clippy::missing_errors_doc)]
+    fn begin_zlib_with_dictionary(&mut self, _dictionary: &[u8]) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "preset dictionaries are not supported by this stream",
+        ))
+    }
     /// Stop zlib decompression on downstream. Ignored if already disabled.
     fn end_zlib(&mut self);
+    /// Whether zlib decompression is currently enabled on downstream.
+    ///
+    /// By default, this is `false`, as if the stream could not tell.
+    fn is_zlib_active(&self) -> bool {
+        false
+    }
 }
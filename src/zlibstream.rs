@@ -1,13 +1,123 @@
 use crate::{stream::Stream, zcstream::ZCStream};
 use flate2::read::ZlibDecoder;
+#[cfg(feature = "zlib-dictionary")]
+use flate2::{Decompress, FlushDecompress, Status};
+#[cfg(feature = "zlib-dictionary")]
+use std::io::ErrorKind;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(windows)]
+use std::os::windows::io::RawSocket;
 use std::{
-    io::{Read, Result, Write},
+    io::{Error, Read, Result, Write},
+    net::{Shutdown, SocketAddr},
     time::Duration,
 };
 
 enum ZlibStreamSwitch<T> {
     Plain(T),
-    Encoded(ZlibDecoder<T>),
+    Encoded(ZlibDecoder<Prefixed<T>>),
+    #[cfg(feature = "zlib-dictionary")]
+    EncodedWithDictionary(DictionaryDecoder<T>),
+}
+
+// A stream which first returns bytes that were already read from it
+struct Prefixed<T> {
+    prefix: Box<[u8]>,
+    prefix_start: usize,
+    stream: T,
+}
+
+impl<T> Prefixed<T> {
+    fn new(stream: T, prefix: &[u8]) -> Self {
+        Prefixed {
+            prefix: Box::from(prefix),
+            prefix_start: 0,
+            stream,
+        }
+    }
+}
+
+impl<T> Read for Prefixed<T>
+where
+    T: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.prefix_start == self.prefix.len() {
+            return self.stream.read(buf);
+        }
+
+        let size = buf.len().min(self.prefix.len() - self.prefix_start);
+        buf[..size].copy_from_slice(&self.prefix[self.prefix_start..self.prefix_start + size]);
+        self.prefix_start += size;
+        Ok(size)
+    }
+}
+
+// A zlib decoder which supplies a preset dictionary when the stream asks for one
+#[cfg(feature = "zlib-dictionary")]
+struct DictionaryDecoder<T> {
+    stream: T,
+    decompress: Decompress,
+    dictionary: Box<[u8]>,
+    input: Box<[u8]>,
+    input_start: usize,
+    input_end: usize,
+}
+
+#[cfg(feature = "zlib-dictionary")]
+impl<T> DictionaryDecoder<T> {
+    fn new(stream: T, dictionary: &[u8]) -> Self {
+        DictionaryDecoder {
+            stream,
+            decompress: Decompress::new(true),
+            dictionary: Box::from(dictionary),
+            input: vec![0; 8 * 1024].into_boxed_slice(),
+            input_start: 0,
+            input_end: 0,
+        }
+    }
+}
+
+#[cfg(feature = "zlib-dictionary")]
+impl<T> Read for DictionaryDecoder<T>
+where
+    T: Read,
+{
+    #[allow(clippy::cast_possible_truncation)]
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.input_start == self.input_end {
+                self.input_end = self.stream.read(&mut self.input)?;
+                self.input_start = 0;
+                if self.input_end == 0 {
+                    return Ok(0);
+                }
+            }
+
+            let in_before = self.decompress.total_in();
+            let out_before = self.decompress.total_out();
+            let result = self.decompress.decompress(
+                &self.input[self.input_start..self.input_end],
+                buf,
+                FlushDecompress::None,
+            );
+            self.input_start += (self.decompress.total_in() - in_before) as usize;
+            let written = (self.decompress.total_out() - out_before) as usize;
+
+            match result {
+                Ok(Status::StreamEnd) => return Ok(written),
+                Ok(_) if written > 0 || buf.is_empty() => return Ok(written),
+                Ok(_) => {}
+                Err(e) if e.needs_dictionary().is_some() => {
+                    self.decompress
+                        .set_dictionary(&self.dictionary)
+                        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                }
+                Err(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+            }
+        }
+    }
 }
 
 /// A wrapper which can enable and disable zlib decompression for downstream at runtime.
@@ -41,6 +151,8 @@ where
         match self.stream {
             ZlibStreamSwitch::Plain(ref mut stream) => stream.read(buf),
             ZlibStreamSwitch::Encoded(ref mut stream) => stream.read(buf),
+            #[cfg(feature = "zlib-dictionary")]
+            ZlibStreamSwitch::EncodedWithDictionary(ref mut stream) => stream.read(buf),
         }
     }
 }
@@ -52,13 +164,28 @@ where
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         match self.stream {
             ZlibStreamSwitch::Plain(ref mut stream) => stream.write(buf),
-            ZlibStreamSwitch::Encoded(ref mut stream) => stream.get_mut().write(buf),
+            ZlibStreamSwitch::Encoded(ref mut stream) => stream.get_mut().stream.write(buf),
+            #[cfg(feature = "zlib-dictionary")]
+            ZlibStreamSwitch::EncodedWithDictionary(ref mut stream) => stream.stream.write(buf),
         }
     }
     fn flush(&mut self) -> Result<()> {
         match self.stream {
             ZlibStreamSwitch::Plain(ref mut stream) => stream.flush(),
-            ZlibStreamSwitch::Encoded(ref mut stream) => stream.get_mut().flush(),
+            ZlibStreamSwitch::Encoded(ref mut stream) => stream.get_mut().stream.flush(),
+            #[cfg(feature = "zlib-dictionary")]
+            ZlibStreamSwitch::EncodedWithDictionary(ref mut stream) => stream.stream.flush(),
+        }
+    }
+}
+
+impl<T> ZlibStream<T> {
+    fn get_ref(&self) -> &T {
+        match self.stream {
+            ZlibStreamSwitch::Plain(ref stream) => stream,
+            ZlibStreamSwitch::Encoded(ref stream) => &stream.get_ref().stream,
+            #[cfg(feature = "zlib-dictionary")]
+            ZlibStreamSwitch::EncodedWithDictionary(ref stream) => &stream.stream,
         }
     }
 }
@@ -68,17 +195,61 @@ where
     T: Stream,
 {
     fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
-        match self.stream {
-            ZlibStreamSwitch::Plain(ref stream) => stream.set_nonblocking(nonblocking),
-            ZlibStreamSwitch::Encoded(ref stream) => stream.get_ref().set_nonblocking(nonblocking),
-        }
+        self.get_ref().set_nonblocking(nonblocking)
     }
 
     fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
-        match self.stream {
-            ZlibStreamSwitch::Plain(ref stream) => stream.set_read_timeout(dur),
-            ZlibStreamSwitch::Encoded(ref stream) => stream.get_ref().set_read_timeout(dur),
-        }
+        self.get_ref().set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        self.get_ref().set_write_timeout(dur)
+    }
+
+    fn write_timeout(&self) -> Result<Option<Duration>> {
+        self.get_ref().write_timeout()
+    }
+
+    fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+        self.get_ref().set_nodelay(nodelay)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<()> {
+        self.get_ref().shutdown(how)
+    }
+
+    fn peer_addr(&self) -> Result<SocketAddr> {
+        self.get_ref().peer_addr()
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.get_ref().local_addr()
+    }
+
+    fn available(&self) -> Result<usize> {
+        self.get_ref().available()
+    }
+
+    fn set_cork(&self, cork: bool) -> Result<()> {
+        self.get_ref().set_cork(cork)
+    }
+
+    fn take_error(&self) -> Result<Option<Error>> {
+        self.get_ref().take_error()
+    }
+
+    fn set_linger(&self, linger: Option<Duration>) -> Result<()> {
+        self.get_ref().set_linger(linger)
+    }
+
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        self.get_ref().as_raw_fd()
+    }
+
+    #[cfg(windows)]
+    fn as_raw_socket(&self) -> Option<RawSocket> {
+        self.get_ref().as_raw_socket()
     }
 }
 
@@ -87,21 +258,115 @@ where
     T: Stream,
 {
     fn begin_zlib(&mut self) {
+        self.begin_zlib_with_input(&[]);
+    }
+    fn begin_zlib_with_input(&mut self, input: &[u8]) {
         replace_with::replace_with_or_abort(&mut self.stream, |stream| {
             if let ZlibStreamSwitch::Plain(stream) = stream {
-                ZlibStreamSwitch::Encoded(ZlibDecoder::new(stream))
+                ZlibStreamSwitch::Encoded(ZlibDecoder::new(Prefixed::new(stream, input)))
             } else {
                 stream
             }
         });
     }
-    fn end_zlib(&mut self) {
+    #[cfg(feature = "zlib-dictionary")]
+    fn begin_zlib_with_dictionary(&mut self, dictionary: &[u8]) -> Result<()> {
         replace_with::replace_with_or_abort(&mut self.stream, |stream| {
-            if let ZlibStreamSwitch::Encoded(stream) = stream {
-                ZlibStreamSwitch::Plain(stream.into_inner())
+            if let ZlibStreamSwitch::Plain(stream) = stream {
+                ZlibStreamSwitch::EncodedWithDictionary(DictionaryDecoder::new(stream, dictionary))
             } else {
                 stream
             }
         });
+        Ok(())
+    }
+    fn is_zlib_active(&self) -> bool {
+        !matches!(self.stream, ZlibStreamSwitch::Plain(_))
+    }
+    fn end_zlib(&mut self) {
+        replace_with::replace_with_or_abort(&mut self.stream, |stream| match stream {
+            ZlibStreamSwitch::Encoded(stream) => {
+                ZlibStreamSwitch::Plain(stream.into_inner().stream)
+            }
+            #[cfg(feature = "zlib-dictionary")]
+            ZlibStreamSwitch::EncodedWithDictionary(stream) => {
+                ZlibStreamSwitch::Plain(stream.stream)
+            }
+            stream @ ZlibStreamSwitch::Plain(_) => stream,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compress, Compression, FlushCompress};
+    use std::io::Cursor;
+
+    struct MockStream(Cursor<Vec<u8>>);
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Stream for MockStream {
+        fn set_nonblocking(&self, _nonblocking: bool) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_read_timeout(&self, _dur: Option<Duration>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "zlib-dictionary")]
+    #[test]
+    fn decompresses_with_dictionary() {
+        let dictionary = b"You see here a ";
+        let text = b"You see here a sword. You see here a shield.";
+
+        let mut compress = Compress::new(Compression::default(), true);
+        compress.set_dictionary(dictionary).unwrap();
+        let mut compressed = Vec::with_capacity(128);
+        compress
+            .compress_vec(text, &mut compressed, FlushCompress::Finish)
+            .unwrap();
+
+        let mut stream = ZlibStream::from_stream(MockStream(Cursor::new(compressed)));
+        stream.begin_zlib_with_dictionary(dictionary).unwrap();
+
+        let mut decompressed = Vec::new();
+        stream.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, text);
+    }
+
+    #[test]
+    fn decompresses_input_read_before_begin() {
+        let text = b"compressed from the start";
+        let mut compress = Compress::new(Compression::default(), true);
+        let mut compressed = Vec::with_capacity(128);
+        compress
+            .compress_vec(text, &mut compressed, FlushCompress::Finish)
+            .unwrap();
+
+        // The first bytes were already read by the caller
+        let (input, rest) = compressed.split_at(5);
+        let mut stream = ZlibStream::from_stream(MockStream(Cursor::new(rest.to_vec())));
+        stream.begin_zlib_with_input(input);
+
+        let mut decompressed = Vec::new();
+        stream.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, text);
     }
 }
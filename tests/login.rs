@@ -0,0 +1,32 @@
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
+};
+use telnet::{Telnet, TelnetOption};
+
+#[test]
+fn connect_and_login_answers_negotiation_before_prompt() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+
+        // IAC DO TTYPE, IAC WILL ECHO
+        socket.write_all(&[255, 253, 24, 255, 251, 1]).unwrap();
+
+        // Only send the prompt once both negotiations are answered
+        let mut reply = [0; 6];
+        socket.read_exact(&mut reply).unwrap();
+        socket.write_all(b"login: ").unwrap();
+
+        reply
+    });
+
+    let (_telnet, prompt) = Telnet::connect_and_login(addr, 256, &[TelnetOption::Echo]).unwrap();
+    assert_eq!(prompt.as_ref(), b"login: ");
+
+    // IAC WONT TTYPE, IAC DO ECHO
+    assert_eq!(server.join().unwrap(), [255, 252, 24, 255, 253, 1]);
+}
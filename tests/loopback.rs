@@ -0,0 +1,115 @@
+use std::{
+    io::{ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+use telnet::{Action, Event, Telnet, TelnetOption};
+
+// Starts a server that writes each fragment as a separate TCP segment and then closes the
+// connection, and returns a `Telnet` connected to it.
+fn serve_fragments(fragments: &'static [&'static [u8]]) -> (Telnet, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.set_nodelay(true).unwrap();
+        for fragment in fragments {
+            socket.write_all(fragment).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    (Telnet::from_tcp_stream(stream, 256), server)
+}
+
+// Reads events until the connection is closed, merging adjacent data events
+fn read_all(telnet: &mut Telnet) -> Vec<Event> {
+    let mut events = Vec::new();
+    loop {
+        match telnet.read() {
+            Ok(Event::Data(data)) => match events.last_mut() {
+                Some(Event::Data(prev)) => {
+                    *prev = [prev.as_ref(), data.as_ref()].concat().into_boxed_slice();
+                }
+                _ => events.push(Event::Data(data)),
+            },
+            Ok(event) => events.push(event),
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+                return events;
+            }
+        }
+    }
+}
+
+#[test]
+fn split_negotiation() {
+    // IAC WILL ECHO, one byte at a time
+    let (mut telnet, server) = serve_fragments(&[&[255], &[251], &[1]]);
+
+    let events = read_all(&mut telnet);
+    assert_eq!(events.len(), 1);
+    assert!(matches!(
+        events[0],
+        Event::Negotiation(Action::Will, TelnetOption::Echo)
+    ));
+
+    server.join().unwrap();
+}
+
+#[test]
+fn fragmented_subnegotiation() {
+    // IAC SB TTYPE SEND IAC SE, split inside the payload and between IAC and SE
+    let (mut telnet, server) =
+        serve_fragments(&[b"ab", &[255, 250, 24], &[1], &[255], &[240, b'c']]);
+
+    let events = read_all(&mut telnet);
+    assert_eq!(events.len(), 3);
+    assert!(matches!(&events[0], Event::Data(data) if data.as_ref() == b"ab"));
+    assert!(matches!(
+        &events[1],
+        Event::Subnegotiation(TelnetOption::TTYPE, data) if data.as_ref() == [1]
+    ));
+    assert!(matches!(&events[2], Event::Data(data) if data.as_ref() == b"c"));
+
+    server.join().unwrap();
+}
+
+#[test]
+fn split_escaped_iac() {
+    let (mut telnet, server) = serve_fragments(&[&[b'a', 255], &[255, b'b']]);
+
+    let events = read_all(&mut telnet);
+    assert_eq!(events.len(), 1);
+    assert!(matches!(&events[0], Event::Data(data) if data.as_ref() == [b'a', 255, b'b']));
+
+    server.join().unwrap();
+}
+
+#[test]
+fn read_timeout_waits_for_split_negotiation() {
+    let (mut telnet, server) = serve_fragments(&[&[255], &[253], &[31]]);
+
+    let event = telnet.read_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(
+        event,
+        Event::Negotiation(Action::Do, TelnetOption::NAWS)
+    ));
+
+    server.join().unwrap();
+}
+
+#[test]
+fn reports_closed_connection() {
+    let (mut telnet, server) = serve_fragments(&[]);
+    server.join().unwrap();
+
+    let err = telnet.read().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+    let err = telnet.read_timeout(Duration::from_secs(1)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
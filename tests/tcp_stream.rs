@@ -0,0 +1,374 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use telnet::{Action, Backoff, Event, Stream, Telnet, TelnetOption};
+
+#[test]
+fn from_tcp_stream_reads_from_loopback() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        // IAC WILL ECHO
+        socket.write_all(&[255, 251, 1]).unwrap();
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_nodelay(true).unwrap();
+    let mut telnet = Telnet::from_tcp_stream(stream, 256);
+
+    let event = telnet.read().unwrap();
+    assert!(matches!(
+        event,
+        Event::Negotiation(Action::Will, TelnetOption::Echo)
+    ));
+
+    server.join().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn exposes_raw_fd_of_loopback_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let telnet = Telnet::connect(listener.local_addr().unwrap(), 256).unwrap();
+
+    assert!(telnet.as_raw_fd().unwrap() >= 0);
+}
+
+#[test]
+fn connect_and_drain_returns_opening_negotiations() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        // IAC DO TTYPE, IAC WILL ECHO, IAC DO NAWS
+        socket
+            .write_all(&[255, 253, 24, 255, 251, 1, 255, 253, 31])
+            .unwrap();
+        socket
+    });
+
+    let (_telnet, events) = Telnet::connect_and_drain(addr, 256, Duration::from_secs(5)).unwrap();
+    let _socket = server.join().unwrap();
+
+    assert_eq!(events.len(), 3);
+    assert!(matches!(
+        events[0],
+        Event::Negotiation(Action::Do, TelnetOption::TTYPE)
+    ));
+    assert!(matches!(
+        events[1],
+        Event::Negotiation(Action::Will, TelnetOption::Echo)
+    ));
+    assert!(matches!(
+        events[2],
+        Event::Negotiation(Action::Do, TelnetOption::NAWS)
+    ));
+}
+
+#[test]
+fn connect_and_drain_times_out_on_silent_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let (_telnet, events) =
+        Telnet::connect_and_drain(addr, 256, Duration::from_millis(50)).unwrap();
+    assert!(events.is_empty());
+    drop(listener);
+}
+
+#[test]
+fn connect_timeout_v6_keeps_scope_id() {
+    // Skip on hosts without IPv6
+    let Ok(listener) = TcpListener::bind("[::1]:0") else {
+        return;
+    };
+    let port = listener.local_addr().unwrap().port();
+
+    let server = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        // IAC WILL ECHO
+        socket.write_all(&[255, 251, 1]).unwrap();
+    });
+
+    // The loopback interface, with a flow label
+    let addr = SocketAddrV6::new(Ipv6Addr::LOCALHOST, port, 7, 1);
+
+    // Resolving the address, as `Telnet::connect` does, keeps both
+    let resolved: Vec<_> = addr.to_socket_addrs().unwrap().collect();
+    assert!(matches!(
+        resolved[..],
+        [SocketAddr::V6(v6)] if v6.scope_id() == 1 && v6.flowinfo() == 7
+    ));
+
+    let mut telnet = Telnet::connect_timeout_v6(&addr, 256, Duration::from_secs(5)).unwrap();
+
+    let event = telnet.read().unwrap();
+    assert!(matches!(
+        event,
+        Event::Negotiation(Action::Will, TelnetOption::Echo)
+    ));
+
+    server.join().unwrap();
+}
+
+// Linux refuses to connect to a link-local address without a scope id
+#[cfg(target_os = "linux")]
+#[test]
+fn connect_timeout_v6_passes_scope_id_to_socket() {
+    let link_local = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    let timeout = Duration::from_millis(100);
+
+    let unscoped = SocketAddrV6::new(link_local, 23, 0, 0);
+    let result = Telnet::connect_timeout_v6(&unscoped, 256, timeout);
+    assert!(matches!(result, Err(e) if e.kind() == io::ErrorKind::InvalidInput));
+
+    // No such interface, so the connection fails further on
+    let scoped = SocketAddrV6::new(link_local, 23, 0, u32::MAX);
+    let result = Telnet::connect_timeout_v6(&scoped, 256, timeout);
+    assert!(matches!(result, Err(e) if e.kind() != io::ErrorKind::InvalidInput));
+}
+
+#[test]
+fn connect_retry_waits_for_listener() {
+    // Find a free port, and only start listening on it after the first attempts failed
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let server = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        let listener = TcpListener::bind(addr).unwrap();
+        let (mut socket, _) = listener.accept().unwrap();
+        // IAC WILL ECHO
+        socket.write_all(&[255, 251, 1]).unwrap();
+    });
+
+    let backoff = Backoff::Fixed(Duration::from_millis(50));
+    let mut telnet = Telnet::connect_retry(addr, 256, 20, backoff).unwrap();
+
+    let event = telnet.read().unwrap();
+    assert!(matches!(
+        event,
+        Event::Negotiation(Action::Will, TelnetOption::Echo)
+    ));
+
+    server.join().unwrap();
+}
+
+#[test]
+fn connect_retry_returns_last_error() {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+
+    let backoff = Backoff::Fixed(Duration::from_millis(1));
+    let result = Telnet::connect_retry(addr, 256, 3, backoff);
+    assert!(matches!(result, Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused));
+}
+
+#[test]
+fn available_counts_bytes_ready_to_read() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let telnet = Telnet::connect(listener.local_addr().unwrap(), 256).unwrap();
+    let (mut socket, _) = listener.accept().unwrap();
+    assert_eq!(telnet.available().unwrap(), 0);
+
+    socket.write_all(b"hello").unwrap();
+    let start = std::time::Instant::now();
+    while telnet.available().unwrap() < 5 && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(telnet.available().unwrap(), 5);
+}
+
+#[test]
+fn deadline_write_restores_write_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (_socket, _) = listener.accept().unwrap();
+
+    let timeout = Some(Duration::from_secs(7));
+    stream.set_write_timeout(timeout).unwrap();
+    let probe = stream.try_clone().unwrap();
+    let mut telnet = Telnet::from_tcp_stream(stream, 256);
+
+    let mut exchange = telnet.with_deadline(Instant::now() + Duration::from_secs(5));
+    exchange.write(b"who").unwrap();
+
+    assert_eq!(probe.write_timeout().unwrap(), timeout);
+}
+
+#[test]
+fn take_error_is_none_on_healthy_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let telnet = Telnet::connect(listener.local_addr().unwrap(), 256).unwrap();
+    let _socket = listener.accept().unwrap();
+    assert!(telnet.take_error().unwrap().is_none());
+}
+
+#[test]
+fn set_linger_on_loopback_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let telnet = Telnet::connect(listener.local_addr().unwrap(), 256).unwrap();
+    let _socket = listener.accept().unwrap();
+
+    telnet
+        .set_linger(Some(Duration::from_millis(1500)))
+        .unwrap();
+    telnet.set_linger(None).unwrap();
+}
+
+// Counts the bytes read and written through a stream
+struct CountingStream {
+    stream: TcpStream,
+    read: Arc<AtomicUsize>,
+    written: Arc<AtomicUsize>,
+}
+
+impl Read for CountingStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.stream.read(buf)?;
+        self.read.fetch_add(len, Ordering::SeqCst);
+        Ok(len)
+    }
+}
+
+impl Write for CountingStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.stream.write(buf)?;
+        self.written.fetch_add(len, Ordering::SeqCst);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Stream for CountingStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(dur)
+    }
+}
+
+#[test]
+fn connect_with_wraps_stream() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.write_all(b"hello").unwrap();
+        let mut buf = [0; 3];
+        socket.read_exact(&mut buf).unwrap();
+    });
+
+    let read = Arc::new(AtomicUsize::new(0));
+    let written = Arc::new(AtomicUsize::new(0));
+    let counters = (Arc::clone(&read), Arc::clone(&written));
+    let mut telnet = Telnet::connect_with(addr, 256, move |stream| {
+        let stream = CountingStream {
+            stream,
+            read: counters.0,
+            written: counters.1,
+        };
+        #[cfg(feature = "zcstream")]
+        return Box::new(telnet::ZlibStream::from_stream(stream));
+        #[cfg(not(feature = "zcstream"))]
+        return Box::new(stream);
+    })
+    .unwrap();
+
+    let event = telnet.read().unwrap();
+    assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"hello"));
+    telnet.write(b"bye").unwrap();
+    server.join().unwrap();
+
+    assert_eq!(read.load(Ordering::SeqCst), 5);
+    assert_eq!(written.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn corked_writes_reach_remote_host_on_uncork() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut telnet = Telnet::connect(listener.local_addr().unwrap(), 256).unwrap();
+    let (mut socket, _) = listener.accept().unwrap();
+
+    telnet.cork().unwrap();
+    telnet.negotiate(&Action::Do, TelnetOption::Echo).unwrap();
+    telnet.write(b"hi").unwrap();
+    telnet.uncork().unwrap();
+
+    let mut buf = [0; 5];
+    socket.read_exact(&mut buf).unwrap();
+    assert_eq!(buf, [255, 253, 1, b'h', b'i']);
+}
+
+#[test]
+fn converts_tcp_stream_into_telnet() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut socket, _) = listener.accept().unwrap();
+
+    let mut telnet: Telnet = stream.into();
+    assert_eq!(telnet.buffer_capacity(), 4096);
+    socket.write_all(b"hi").unwrap();
+    let event = telnet.read().unwrap();
+    assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"hi"));
+}
+
+#[test]
+fn connect_with_resolver_uses_resolved_addresses() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let mut resolved = None;
+    let telnet = Telnet::connect_with_resolver("bbs.invalid", port, 256, |host| {
+        resolved = Some(host.to_string());
+        Ok(vec![(Ipv4Addr::LOCALHOST, 0).into()])
+    });
+    assert!(telnet.is_ok());
+    assert_eq!(resolved.as_deref(), Some("bbs.invalid"));
+    listener.accept().unwrap();
+}
+
+#[test]
+fn connect_configured_sets_socket_options() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.write_all(b"hello").unwrap();
+    });
+
+    let mut configured = 0;
+    let mut telnet = Telnet::connect_configured(addr, 256, |socket| {
+        configured += 1;
+        socket.set_recv_buffer_size(8192)?;
+        assert!(socket.recv_buffer_size()? >= 8192);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(configured, 1);
+
+    let event = telnet.read_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(event, Event::Data(ref data) if data.as_ref() == b"hello"));
+    server.join().unwrap();
+}